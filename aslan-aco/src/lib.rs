@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use aslan_data::{DataNode, Direction, EdgeWeighting};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+//ant: walks the graph from the start of a chunk of data, picking edges by pheromone and weight
+//pheromone: learned desirability of an edge, ants that track the data closely deposit more
+//evaporation: share of the pheromone every edge loses per iteration
+//the refined model is the original scores scaled by the pheromone on each edge
+
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum UpdateStrategy {
+    // every ant deposits 1 / (1 + error)
+    AntSystem,
+    // every ant deposits and the best path found so far deposits again with extra weight
+    Elitist { elite_weight: f64 },
+    // only the best ranked ants deposit, scaled by their rank
+    RankBased { ranks: usize },
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+#[serde(default)]
+pub struct AntColony {
+    pub ants: usize,
+    pub iterations: usize,
+    pub evaporation: f64,
+    // how strongly pheromone and the trained weights steer the ants
+    pub pheromone_influence: f64,
+    pub weight_influence: f64,
+    // pheromone never falls below this so no edge becomes unreachable
+    pub min_pheromone: f64,
+    pub strategy: UpdateStrategy,
+    // stop once the mean error has not improved by more than tolerance for this many iterations
+    pub patience: usize,
+    pub tolerance: f64,
+}
+
+// pheromone per edge, laid out like the forward edges of the nodes
+#[derive(Debug,Clone)]
+pub struct PheromoneMatrix {
+    pub trails: Vec<Vec<f64>>,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct ColonyIteration {
+    pub iteration: usize,
+    pub best_error: f64,
+    pub mean_error: f64,
+}
+
+#[derive(Debug,Clone)]
+pub struct ColonyResult {
+    pub nodes: Vec<DataNode>,
+    pub trace: Vec<ColonyIteration>,
+    pub best_error: f64,
+    pub converged: bool,
+}
+
+#[derive(Debug,Clone)]
+struct AntPath {
+    // node index and edge index of every step
+    steps: Vec<(usize, usize)>,
+    error: f64,
+}
+
+impl Default for AntColony {
+    fn default() -> Self {
+        AntColony {
+            ants: 20,
+            iterations: 100,
+            evaporation: 0.1,
+            pheromone_influence: 1.0,
+            weight_influence: 1.0,
+            min_pheromone: 0.01,
+            strategy: UpdateStrategy::AntSystem,
+            patience: 10,
+            tolerance: 0.0001,
+        }
+    }
+}
+
+impl PheromoneMatrix {
+    pub fn new(nodes: &Vec<DataNode>) -> Self {
+        PheromoneMatrix {
+            trails: nodes.iter().map(|x| vec![1.0; x.get_edges(Direction::Forward).len()]).collect(),
+        }
+    }
+
+    pub fn evaporate(&mut self, evaporation: f64, min_pheromone: f64) {
+        for trail in self.trails.iter_mut() {
+            for pheromone in trail.iter_mut() {
+                *pheromone = (*pheromone * (1.0 - evaporation)).max(min_pheromone);
+            }
+        }
+    }
+
+    fn deposit(&mut self, path: &AntPath, amount: f64) {
+        for (node_index, edge_index) in path.steps.iter() {
+            self.trails[*node_index][*edge_index] += amount;
+        }
+    }
+}
+
+impl AntColony {
+    // data is the normalized series the model was trained on
+    pub fn run<R: Rng + ?Sized>(&self, nodes: &Vec<DataNode>, data: &Vec<f64>, chunk_size: usize, weighting: &EdgeWeighting, rng: &mut R) -> ColonyResult {
+        let chunks: Vec<&[f64]> = data.chunks(chunk_size).filter(|x| x.len() > 1).collect();
+        let mut matrix = PheromoneMatrix::new(nodes);
+        let mut trace = Vec::new();
+        let mut best_path: Option<AntPath> = None;
+        let mut best_mean_error = f64::MAX;
+        let mut stale_iterations = 0;
+        let mut converged = false;
+
+        if chunks.is_empty() || nodes.is_empty() {
+            return ColonyResult { nodes: nodes.to_vec(), trace, best_error: f64::MAX, converged };
+        }
+
+        let node_indexes: HashMap<String, usize> = nodes.iter().enumerate().map(|(i, x)| (x.average.to_string(), i)).collect();
+
+        for iteration in 0..self.iterations {
+            let mut paths: Vec<AntPath> = (0..self.ants).map(|_| {
+                let chunk = chunks[rng.gen_range(0..chunks.len())];
+                self.walk(nodes, &node_indexes, &matrix, chunk, rng)
+            }).collect();
+            paths.sort_by(|a, b| a.error.partial_cmp(&b.error).unwrap_or(std::cmp::Ordering::Equal));
+
+            if let Some(best) = paths.first() {
+                if best_path.as_ref().map_or(true, |x| best.error < x.error) {
+                    best_path = Some(best.clone());
+                }
+            }
+
+            matrix.evaporate(self.evaporation, self.min_pheromone);
+            match self.strategy {
+                UpdateStrategy::AntSystem => {
+                    for path in paths.iter() {
+                        matrix.deposit(path, AntColony::deposit_amount(path.error));
+                    }
+                },
+                UpdateStrategy::Elitist { elite_weight } => {
+                    for path in paths.iter() {
+                        matrix.deposit(path, AntColony::deposit_amount(path.error));
+                    }
+                    if let Some(best) = best_path.as_ref() {
+                        matrix.deposit(best, elite_weight * AntColony::deposit_amount(best.error));
+                    }
+                },
+                UpdateStrategy::RankBased { ranks } => {
+                    for (rank, path) in paths.iter().take(ranks).enumerate() {
+                        matrix.deposit(path, (ranks - rank) as f64 * AntColony::deposit_amount(path.error));
+                    }
+                },
+            }
+
+            let mean_error = paths.iter().map(|x| x.error).sum::<f64>() / paths.len().max(1) as f64;
+            trace.push(ColonyIteration {
+                iteration,
+                best_error: best_path.as_ref().map_or(f64::MAX, |x| x.error),
+                mean_error,
+            });
+
+            if best_mean_error - mean_error > self.tolerance {
+                best_mean_error = mean_error;
+                stale_iterations = 0;
+            } else {
+                stale_iterations += 1;
+                if stale_iterations >= self.patience {
+                    converged = true;
+                    break;
+                }
+            }
+        }
+
+        let mut refined = nodes.to_vec();
+        AntColony::apply_pheromones(&mut refined, &matrix);
+        DataNode::set_weights(&mut refined, weighting);
+
+        ColonyResult {
+            nodes: refined,
+            trace,
+            best_error: best_path.map_or(f64::MAX, |x| x.error),
+            converged,
+        }
+    }
+
+    // walks from the first entry of the chunk and scores the walk against the rest of it
+    fn walk<R: Rng + ?Sized>(&self, nodes: &Vec<DataNode>, node_indexes: &HashMap<String, usize>, matrix: &PheromoneMatrix, chunk: &[f64], rng: &mut R) -> AntPath {
+        let mut steps = Vec::new();
+        let mut generated = Vec::new();
+        let mut node_index = AntColony::find_node(nodes, node_indexes, chunk[0]);
+
+        for _ in 1..chunk.len() {
+            let edges = nodes[node_index].get_edges(Direction::Forward);
+            if edges.is_empty() {
+                break;
+            }
+            let attractiveness: Vec<f64> = edges.iter().zip(matrix.trails[node_index].iter())
+                .map(|(edge, pheromone)| pheromone.powf(self.pheromone_influence) * edge.weight.max(0.0).powf(self.weight_influence))
+                .collect();
+            let edge_index = AntColony::choose(&attractiveness, rng);
+
+            steps.push((node_index, edge_index));
+            generated.push(edges[edge_index].value);
+            node_index = AntColony::find_node(nodes, node_indexes, edges[edge_index].value);
+        }
+
+        AntPath {
+            steps,
+            error: AntColony::path_error(&generated, &chunk[1..]),
+        }
+    }
+
+    // root mean square error between the cumulative moves of the walk and of the data
+    fn path_error(generated: &Vec<f64>, actual: &[f64]) -> f64 {
+        if generated.is_empty() {
+            return f64::MAX;
+        }
+        let mut generated_total = 0.0;
+        let mut actual_total = 0.0;
+        let mut sum = 0.0;
+        for (generated_value, actual_value) in generated.iter().zip(actual.iter()) {
+            generated_total += generated_value;
+            actual_total += actual_value;
+            sum += (generated_total - actual_total).powi(2);
+        }
+        (sum / generated.len() as f64).sqrt()
+    }
+
+    // bounded by 1 so a walk that matches the data exactly does not swamp every other trail
+    fn deposit_amount(error: f64) -> f64 {
+        1.0 / (1.0 + error)
+    }
+
+    fn choose<R: Rng + ?Sized>(attractiveness: &Vec<f64>, rng: &mut R) -> usize {
+        let total: f64 = attractiveness.iter().sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return rng.gen_range(0..attractiveness.len());
+        }
+        let mut random_number = rng.gen_range(0.0..total);
+        for (index, value) in attractiveness.iter().enumerate() {
+            random_number -= value;
+            if random_number <= 0.0 {
+                return index;
+            }
+        }
+        attractiveness.len() - 1
+    }
+
+    fn find_node(nodes: &Vec<DataNode>, node_indexes: &HashMap<String, usize>, value: f64) -> usize {
+        if let Some(index) = node_indexes.get(&value.to_string()) {
+            return *index;
+        }
+        let mut index = 0;
+        for i in 0..nodes.len() {
+            if (nodes[i].average - value).abs() < (nodes[index].average - value).abs() {
+                index = i;
+            }
+        }
+        index
+    }
+
+    // scales every forward edge score by its pheromone relative to the node's mean pheromone
+    fn apply_pheromones(nodes: &mut Vec<DataNode>, matrix: &PheromoneMatrix) {
+        for (node, trail) in nodes.iter_mut().zip(matrix.trails.iter()) {
+            if trail.is_empty() {
+                continue;
+            }
+            let mean = trail.iter().sum::<f64>() / trail.len() as f64;
+            for (edge, pheromone) in node.get_edges_mut(Direction::Forward).iter_mut().zip(trail.iter()) {
+                edge.score *= pheromone / mean;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aslan_data::Edge;
+
+    // two nodes that each have a single forward edge, so every walk takes the same steps
+    fn cycle() -> Vec<DataNode> {
+        let mut first = DataNode::new(0.0);
+        let mut second = DataNode::new(1.0);
+        let mut edge = Edge::new(1.0);
+        edge.score = 1.0;
+        edge.weight = 1.0;
+        first.forward_edges.push(edge);
+        let mut edge = Edge::new(0.0);
+        edge.score = 1.0;
+        edge.weight = 1.0;
+        second.forward_edges.push(edge);
+        vec![first, second]
+    }
+
+    #[test]
+    fn deposits_follow_the_path_and_evaporation_keeps_the_floor() {
+        let mut matrix = PheromoneMatrix::new(&cycle());
+        assert_eq!(matrix.trails, vec![vec![1.0], vec![1.0]]);
+
+        let path = AntPath { steps: vec![(0, 0), (0, 0)], error: 1.0 };
+        matrix.deposit(&path, AntColony::deposit_amount(path.error));
+        assert_eq!(matrix.trails, vec![vec![2.0], vec![1.0]]);
+
+        matrix.evaporate(0.5, 0.6);
+        assert_eq!(matrix.trails, vec![vec![1.0], vec![0.6]]);
+    }
+
+    #[test]
+    fn deposits_stay_bounded() {
+        assert_eq!(AntColony::deposit_amount(0.0), 1.0);
+        assert!(AntColony::deposit_amount(1.0) < AntColony::deposit_amount(0.5));
+        assert!(AntColony::deposit_amount(f64::MAX) >= 0.0);
+    }
+
+    #[test]
+    fn stops_after_patience_iterations_without_improvement() {
+        let colony = AntColony { ants: 4, iterations: 100, patience: 3, ..AntColony::default() };
+        // every walk takes the same steps, so the mean error never improves after the first iteration
+        let data = vec![0.0, 1.0, -1.0, 1.0, 0.0, 1.0, -1.0, 1.0];
+        let mut rng = StdRng::seed_from_u64(7);
+        let result = colony.run(&cycle(), &data, 4, &EdgeWeighting::default(), &mut rng);
+
+        assert!(result.converged);
+        assert_eq!(result.trace.len(), 4);
+        assert!(result.best_error.is_finite());
+        assert!(result.trace.iter().all(|x| x.mean_error == result.trace[0].mean_error));
+    }
+
+}
@@ -0,0 +1,227 @@
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::Bootstrap;
+
+// an event a simulated path can run into after the seed
+// above and below are levels, drawdown and rally are shares of the highest or lowest level seen so far,
+// so a 2% drawdown is Drawdown { fraction: 0.02 }
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum Barrier {
+    Above { level: f64 },
+    Below { level: f64 },
+    Drawdown { fraction: f64 },
+    Rally { fraction: f64 },
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct BarrierProbability {
+    pub barrier: Barrier,
+    // share of the paths that hit the barrier within the simulated steps
+    pub probability: f64,
+    // share of the paths that hit it at or before every step, the first entry is the first step after the seed
+    pub hit_by_step: Vec<f64>,
+    // average step of the first hit over the paths that hit it, none when no path did
+    pub expected_first_passage: Option<f64>,
+    // share of the paths that hit this barrier before any of the others, a tie is split between the barriers
+    pub hit_first: f64,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct HistogramBin {
+    pub lower: f64,
+    pub upper: f64,
+    pub probability: f64,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct TerminalDistribution {
+    pub mean: f64,
+    pub standard_deviation: f64,
+    pub min: f64,
+    pub max: f64,
+    // one value per quantile of the bootstrap
+    pub quantiles: Vec<(f64, f64)>,
+    pub histogram: Vec<HistogramBin>,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct EventAnalysis {
+    pub path_count: usize,
+    pub barriers: Vec<BarrierProbability>,
+    // share of the paths that hit none of the barriers
+    pub no_hit: f64,
+    pub terminal: TerminalDistribution,
+}
+
+impl Barrier {
+    // the first step after the seed at which the path is at or past the barrier
+    fn first_passage(&self, levels: &Vec<f64>) -> Option<usize> {
+        let mut highest = levels[0];
+        let mut lowest = levels[0];
+        for (step, level) in levels.iter().enumerate().skip(1) {
+            highest = highest.max(*level);
+            lowest = lowest.min(*level);
+            let hit = match self {
+                Barrier::Above { level: barrier } => level >= barrier,
+                Barrier::Below { level: barrier } => level <= barrier,
+                Barrier::Drawdown { fraction } => highest > 0.0 && (highest - level) / highest >= *fraction,
+                Barrier::Rally { fraction } => lowest > 0.0 && (level - lowest) / lowest >= *fraction,
+            };
+            if hit {
+                return Some(step);
+            }
+        }
+        None
+    }
+}
+
+impl Bootstrap {
+    // simulates iterations paths of slot_size steps from the seed level, drawn the same way run draws them,
+    // and measures how often and how soon they hit the barriers and where they end up
+    pub fn simulate_events<R: Rng + ?Sized>(&self, seed_data: f64, slot_size: usize, barriers: &Vec<Barrier>, bins: usize, rng: &mut R) -> EventAnalysis {
+        let paths: Vec<Vec<f64>> = self.sample_paths(slot_size, rng).iter().map(|moves| {
+            let mut level = seed_data;
+            let mut levels = Vec::with_capacity(moves.len() + 1);
+            levels.push(level);
+            for x in moves.iter() {
+                level += x;
+                levels.push(level);
+            }
+            levels
+        }).collect();
+        let path_count = paths.len().max(1) as f64;
+
+        let passages: Vec<Vec<Option<usize>>> = paths.iter().map(|levels| barriers.iter().map(|x| x.first_passage(levels)).collect()).collect();
+
+        // credit the barriers hit on the earliest step of every path
+        let mut hit_first = vec![0.0; barriers.len()];
+        let mut no_hit = 0.0;
+        for passage in passages.iter() {
+            match passage.iter().flatten().min() {
+                Some(first) => {
+                    let tied = passage.iter().filter(|x| **x == Some(*first)).count() as f64;
+                    for (index, step) in passage.iter().enumerate() {
+                        if *step == Some(*first) {
+                            hit_first[index] += 1.0 / tied;
+                        }
+                    }
+                },
+                None => no_hit += 1.0,
+            }
+        }
+
+        let barriers = barriers.iter().enumerate().map(|(index, barrier)| {
+            let steps: Vec<usize> = passages.iter().filter_map(|x| x[index]).collect();
+            BarrierProbability {
+                barrier: *barrier,
+                probability: steps.len() as f64 / path_count,
+                hit_by_step: (1..=slot_size).map(|step| steps.iter().filter(|x| **x <= step).count() as f64 / path_count).collect(),
+                expected_first_passage: if steps.is_empty() { None } else { Some(steps.iter().sum::<usize>() as f64 / steps.len() as f64) },
+                hit_first: hit_first[index] / path_count,
+            }
+        }).collect();
+
+        let terminal: Vec<f64> = paths.iter().map(|x| x[x.len() - 1]).collect();
+        EventAnalysis {
+            path_count: paths.len(),
+            barriers,
+            no_hit: no_hit / path_count,
+            terminal: self.terminal_distribution(&terminal, bins),
+        }
+    }
+
+    fn terminal_distribution(&self, terminal: &Vec<f64>, bins: usize) -> TerminalDistribution {
+        let count = terminal.len().max(1) as f64;
+        let mean = terminal.iter().sum::<f64>() / count;
+        let min = terminal.iter().cloned().fold(f64::MAX, f64::min);
+        let max = terminal.iter().cloned().fold(f64::MIN, f64::max);
+
+        // equal width bins between the lowest and highest terminal value, the last one includes the highest
+        let bins = bins.max(1);
+        let width = (max - min) / bins as f64;
+        let mut counts = vec![0usize; bins];
+        for value in terminal.iter() {
+            let bin = if width > 0.0 { ((value - min) / width) as usize } else { 0 };
+            counts[bin.min(bins - 1)] += 1;
+        }
+
+        TerminalDistribution {
+            mean,
+            standard_deviation: (terminal.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / count).sqrt(),
+            min,
+            max,
+            quantiles: self.quantiles.iter().map(|quantile| (*quantile, Bootstrap::quantile(terminal, *quantile))).collect(),
+            histogram: counts.iter().enumerate().map(|(index, bin_count)| HistogramBin {
+                lower: min + width * index as f64,
+                upper: min + width * (index + 1) as f64,
+                probability: *bin_count as f64 / count,
+            }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BootstrapMethod;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn barriers_hit_on_the_same_step_share_the_first_hit() {
+        // every path goes 10, 11, 12, 9
+        let bootstrap = Bootstrap::new(4, vec![vec![1.0, 1.0, -3.0]; 3]).with_method(BootstrapMethod::WholePath);
+        let barriers = vec![
+            Barrier::Above { level: 11.0 },
+            Barrier::Rally { fraction: 0.1 },
+            Barrier::Below { level: 9.5 },
+            Barrier::Drawdown { fraction: 0.5 },
+        ];
+        let events = bootstrap.simulate_events(10.0, 3, &barriers, 4, &mut StdRng::seed_from_u64(1));
+
+        assert_eq!(events.path_count, 4);
+        assert_eq!(events.no_hit, 0.0);
+        let probability: Vec<f64> = events.barriers.iter().map(|x| x.probability).collect();
+        assert_eq!(probability, vec![1.0, 1.0, 1.0, 0.0]);
+        let hit_first: Vec<f64> = events.barriers.iter().map(|x| x.hit_first).collect();
+        assert_eq!(hit_first, vec![0.5, 0.5, 0.0, 0.0]);
+        let first_passage: Vec<Option<f64>> = events.barriers.iter().map(|x| x.expected_first_passage).collect();
+        assert_eq!(first_passage, vec![Some(1.0), Some(1.0), Some(3.0), None]);
+        assert_eq!(events.barriers[2].hit_by_step, vec![0.0, 0.0, 1.0]);
+        assert_eq!(events.terminal.mean, 9.0);
+    }
+
+    #[test]
+    fn paths_that_hit_nothing() {
+        let bootstrap = Bootstrap::new(5, vec![vec![0.5, -0.5]]).with_method(BootstrapMethod::WholePath);
+        let events = bootstrap.simulate_events(10.0, 2, &vec![Barrier::Above { level: 11.0 }, Barrier::Below { level: 9.0 }], 4, &mut StdRng::seed_from_u64(1));
+        assert_eq!(events.no_hit, 1.0);
+        assert!(events.barriers.iter().all(|x| x.probability == 0.0 && x.hit_first == 0.0));
+    }
+
+    #[test]
+    fn quantiles_interpolate_between_sorted_values() {
+        let samples = vec![4.0, 1.0, 3.0, 2.0];
+        assert_eq!(Bootstrap::quantile(&samples, 0.0), 1.0);
+        assert_eq!(Bootstrap::quantile(&samples, 0.25), 1.75);
+        assert_eq!(Bootstrap::quantile(&samples, 0.5), 2.5);
+        assert_eq!(Bootstrap::quantile(&samples, 1.0), 4.0);
+        assert_eq!(Bootstrap::quantile(&samples, 2.0), 4.0);
+        assert_eq!(Bootstrap::quantile(&vec![3.0], 0.3), 3.0);
+        assert!(Bootstrap::quantile(&Vec::new(), 0.5).is_nan());
+    }
+
+    #[test]
+    fn terminal_histogram_includes_the_highest_value() {
+        let bootstrap = Bootstrap::new(1, Vec::new()).with_quantiles(vec![0.25, 0.5]);
+        let terminal = bootstrap.terminal_distribution(&vec![1.0, 2.0, 3.0, 4.0], 3);
+
+        assert_eq!(terminal.mean, 2.5);
+        assert!((terminal.standard_deviation - 1.25f64.sqrt()).abs() < 1e-12);
+        assert_eq!((terminal.min, terminal.max), (1.0, 4.0));
+        assert_eq!(terminal.quantiles, vec![(0.25, 1.75), (0.5, 2.5)]);
+        let probability: Vec<f64> = terminal.histogram.iter().map(|x| x.probability).collect();
+        assert_eq!(probability, vec![0.25, 0.25, 0.5]);
+        assert_eq!(terminal.histogram[2].upper, 4.0);
+    }
+}
@@ -1,5 +1,3 @@
-use std::collections::{hash_map, HashMap};
-
 //find the trend of the data
 //iterate through the options
 //randomly select a result
@@ -7,15 +5,137 @@ use std::collections::{hash_map, HashMap};
 //average out the results
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+
+mod ranking;
+mod events;
+pub use ranking::{PathError, PathMetric, RankedCandidate};
+pub use events::{Barrier, BarrierProbability, EventAnalysis, HistogramBin, TerminalDistribution};
+
+// how the sampled paths are put together from the rows
+// per slot draws every step from its own row, which loses how consecutive steps move together
+// whole path keeps one row for every step, moving block switches rows every block_length steps,
+// stationary switches rows after geometrically distributed runs of mean_block_length steps on average
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum BootstrapMethod{
+    PerSlot,
+    WholePath,
+    MovingBlock { block_length: usize },
+    Stationary { mean_block_length: f64 },
+}
+
+impl Default for BootstrapMethod {
+    fn default() -> Self {
+        BootstrapMethod::PerSlot
+    }
+}
+
+// when the adaptive bootstrap stops, estimate compares the largest change of the mean levels between rounds
+// with the tolerance, standard error the largest standard error of the mean levels
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub struct Convergence{
+    pub tolerance: f64,
+    pub max_rounds: usize,
+    #[serde(default)]
+    pub criterion: ConvergenceCriterion,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum ConvergenceCriterion{
+    Estimate,
+    StandardError,
+}
+
+impl Default for ConvergenceCriterion {
+    fn default() -> Self {
+        ConvergenceCriterion::Estimate
+    }
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct ConvergenceRound{
+    pub round: usize,
+    // paths pooled so far
+    pub sample_count: usize,
+    // none in the first round
+    pub estimate_change: Option<f64>,
+    pub standard_error: f64,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct ConvergenceTrace{
+    pub rounds: Vec<ConvergenceRound>,
+    // false when the rounds ran out first
+    pub converged: bool,
+}
+
+// turns candidate scores into draw probabilities, lower scores are better as in the wavereduce summaries
+// softmax weighs a row by exp(-score / temperature), rank by how many rows score worse than it plus one
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum ScoreWeighting{
+    Softmax { temperature: f64 },
+    Rank,
+}
+
 #[derive(Debug)]
 pub struct Bootstrap{
     iterations: usize,
     data: Vec<Vec<f64>>,
+    method: BootstrapMethod,
+    // running totals of the row weights, rows are equally likely without them
+    cumulative_weights: Option<Vec<f64>>,
+    // quantiles of the sampled levels returned as bands
+    quantiles: Vec<f64>,
 }
-#[derive(Debug,Serialize, Deserialize)]
+#[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct BootstrapResult{
     pub generated_data: Vec<f64>,
     pub average_data: Vec<f64>,
+    // the bands, standard error and sample count line up with generated_data, the seed is the first step
+    #[serde(default)]
+    pub bands: Vec<QuantileBand>,
+    // standard error of the mean level at every step
+    #[serde(default)]
+    pub standard_error: Vec<f64>,
+    // samples drawn for every step
+    #[serde(default)]
+    pub sample_count: usize,
+    #[serde(default)]
+    pub method: BootstrapMethod,
+    // one trace per adaptive bootstrap behind the result, in the order the results were combined
+    #[serde(default)]
+    pub convergence: Vec<ConvergenceTrace>,
+}
+
+// the level at every step that the given share of the sampled paths stays below
+#[derive(Debug,Serialize, Deserialize,Clone,PartialEq)]
+pub struct QuantileBand{
+    pub quantile: f64,
+    pub values: Vec<f64>,
+}
+
+impl ScoreWeighting {
+    pub fn weights(&self, scores:&Vec<f64>)->Vec<f64>{
+        match self {
+            ScoreWeighting::Softmax { temperature } => {
+                let temperature = if *temperature > 0.0 { *temperature } else { f64::MIN_POSITIVE };
+                // shifted by the best score so the weights do not underflow
+                let best = scores.iter().cloned().filter(|x| x.is_finite()).fold(f64::MAX, f64::min);
+                scores.iter().map(|x| if x.is_finite() { (-(x - best) / temperature).exp() } else { 0.0 }).collect()
+            },
+            ScoreWeighting::Rank => {
+                let mut sorted:Vec<f64> = scores.iter().cloned().filter(|x| x.is_finite()).collect();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let unusable = scores.len() - sorted.len();
+                // tied scores share a rank
+                scores.iter().map(|x| {
+                    if !x.is_finite() {
+                        return 0.0;
+                    }
+                    (sorted.len() - sorted.partition_point(|y| y <= x) + unusable) as f64 + 1.0
+                }).collect()
+            },
+        }
+    }
 }
 
 impl BootstrapResult {
@@ -24,8 +144,53 @@ impl BootstrapResult {
         BootstrapResult{
             generated_data: generated,
             average_data: average_data,
+            bands: Vec::new(),
+            standard_error: Vec::new(),
+            sample_count: 0,
+            method: BootstrapMethod::default(),
+            convergence: Vec::new(),
         }
     }
+
+    // averages the results step by step, bands are averaged per quantile
+    // and the standard errors are those of the average of independent estimates
+    pub fn combine(results: &Vec<BootstrapResult>) -> BootstrapResult {
+        let count = results.len() as f64;
+        let average = |rows: Vec<&Vec<f64>>| -> Vec<f64> {
+            let length = rows.iter().map(|x| x.len()).min().unwrap_or(0);
+            (0..length).map(|i| rows.iter().map(|x| x[i]).sum::<f64>() / count).collect()
+        };
+        let round = |values: Vec<f64>| -> Vec<f64> { values.iter().map(|x| (x * 100.0).round() / 100.0).collect() };
+        if results.is_empty() {
+            return BootstrapResult::new(Vec::new(), Vec::new());
+        }
+
+        let bands = results[0].bands.iter().enumerate().map(|(index, band)| QuantileBand {
+            quantile: band.quantile,
+            values: round(average(results.iter().map(|x| &x.bands[index].values).collect())),
+        }).collect();
+        let length = results.iter().map(|x| x.standard_error.len()).min().unwrap_or(0);
+        let standard_error = (0..length).map(|i| results.iter().map(|x| x.standard_error[i].powi(2)).sum::<f64>().sqrt() / count).collect();
+
+        BootstrapResult{
+            generated_data: round(average(results.iter().map(|x| &x.generated_data).collect())),
+            average_data: round(average(results.iter().map(|x| &x.average_data).collect())),
+            bands,
+            standard_error,
+            sample_count: results.iter().map(|x| x.sample_count).sum(),
+            method: results[0].method,
+            convergence: results.iter().flat_map(|x| x.convergence.iter().cloned()).collect(),
+        }
+    }
+
+    // the steps in the opposite order, for results generated walking backwards
+    pub fn reverse(&mut self) {
+        self.generated_data.reverse();
+        for band in self.bands.iter_mut() {
+            band.values.reverse();
+        }
+        self.standard_error.reverse();
+    }
 }
 
 impl Bootstrap {
@@ -34,65 +199,357 @@ impl Bootstrap {
         Bootstrap{
             iterations,
             data,
+            method: BootstrapMethod::default(),
+            cumulative_weights: None,
+            quantiles: vec![0.05, 0.25, 0.5, 0.75, 0.95],
+        }
+    }
+
+    // one score per row, in the order of the rows
+    pub fn with_scores(mut self, scores:&Vec<f64>, weighting:ScoreWeighting)->Self{
+        let weights = weighting.weights(scores);
+        let mut total = 0.0;
+        let cumulative:Vec<f64> = weights.iter().map(|x| {
+            total += x;
+            total
+        }).collect();
+        // scores that do not match the rows or give no usable weight leave the rows equally likely
+        self.cumulative_weights = if cumulative.len() == self.data.len() && total > 0.0 && total.is_finite() {
+            Some(cumulative)
+        } else {
+            None
+        };
+        self
+    }
+
+    fn draw_row<R: Rng + ?Sized>(&self, rng:&mut R)->usize{
+        match &self.cumulative_weights {
+            Some(cumulative) => {
+                let random_number = rng.gen_range(0.0..cumulative[cumulative.len() - 1]);
+                cumulative.partition_point(|x| *x <= random_number).min(cumulative.len() - 1)
+            },
+            None => rng.gen_range(0..self.data.len()),
         }
     }
 
+    pub fn with_method(mut self, method:BootstrapMethod)->Self{
+        self.method = method;
+        self
+    }
+
+    pub fn with_quantiles(mut self, quantiles:Vec<f64>)->Self{
+        self.quantiles = quantiles;
+        self
+    }
+
+    // levels from the seed, one more than there are moves
     pub fn denormalize(seed_data:f64,data:Vec<f64>)->Vec<f64>{
-        let mut generated_data = Vec::new();
+        let mut generated_data = Vec::with_capacity(data.len() + 1);
         generated_data.push(seed_data);
-        for i in 1..data.len(){
-            generated_data.push(generated_data[i-1] + data[i]);
+        for value in data{
+            generated_data.push(generated_data[generated_data.len()-1] + value);
         }
         generated_data
     }
 
-    pub fn select_top_results(data: &Vec<Vec<f64>>, test_data:&Vec<f64>,number_to_select:usize)->HashMap<String, Vec<f64>>{
-        let data = Bootstrap::calculate_std_dev(data,test_data);
-        let keys = data.keys().collect::<Vec<&String>>();
-        let mut keys_f64 = keys.iter().map(|x| x.parse::<f64>().unwrap()).collect::<Vec<f64>>();
-        keys_f64.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let mut top_results = HashMap::new();
-        for key in keys_f64.iter().take(number_to_select){
-            top_results.insert(key.to_string(),data.get(&key.to_string()).unwrap().to_vec());
-        }
-        top_results
+    // the number_to_select rows closest to the test data, closest first
+    pub fn select_top_results(data: &Vec<Vec<f64>>, test_data:&Vec<f64>, number_to_select:usize, metric:&dyn PathMetric)->Vec<RankedCandidate>{
+        let mut ranked = Bootstrap::rank_candidates(data, test_data, metric);
+        ranked.truncate(number_to_select);
+        ranked
     }
 
-    pub fn calculate_std_dev(data: &Vec<Vec<f64>>, test_data:&Vec<f64>)->HashMap<String, Vec<f64>>{
-        let mut result  = HashMap::new();
-    
-        for row in data.iter(){
-            let mut generated_data = Bootstrap::denormalize(test_data[0],row.to_vec());
-            let std_dev = generated_data.iter().zip(test_data.iter()).map(|(a,b)| (a - b).powi(2)).sum::<f64>().sqrt();
-            result.insert(std_dev.to_string(),generated_data.to_vec());
+    pub fn run<R: Rng + ?Sized>(&self, seed_data:f64, slot_size:usize, rng:&mut R) -> BootstrapResult {
+        let samples = self.sample_paths(slot_size, rng);
+        self.summarize(seed_data, slot_size, &samples)
+    }
+
+    // draws rounds of iterations paths and pools them until the estimates settle or the rounds run out
+    // the result covers every pooled path and records how the estimates moved round by round
+    pub fn run_until_converged<R: Rng + ?Sized>(&self, seed_data:f64, slot_size:usize, convergence:&Convergence, rng:&mut R) -> BootstrapResult {
+        let mut samples = Vec::new();
+        let mut rounds = Vec::new();
+        let mut previous_levels: Option<Vec<f64>> = None;
+        let mut converged = false;
+
+        for round in 1..=convergence.max_rounds.max(1){
+            samples.extend(self.sample_paths(slot_size, rng));
+            let levels = Bootstrap::mean_levels(&samples, slot_size);
+            let standard_error = Bootstrap::cumulative_levels(&samples, slot_size).iter().map(|x| Bootstrap::standard_error(x)).fold(0.0, f64::max);
+            // the first round has nothing to compare against
+            let estimate_change = previous_levels.map(|previous| previous.iter().zip(levels.iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max));
+
+            rounds.push(ConvergenceRound { round, sample_count: samples.len(), estimate_change, standard_error });
+            converged = match convergence.criterion {
+                ConvergenceCriterion::Estimate => estimate_change.map_or(false, |x| x < convergence.tolerance),
+                ConvergenceCriterion::StandardError => standard_error < convergence.tolerance,
+            };
+            if converged {
+                break;
+            }
+            previous_levels = Some(levels);
         }
+
+        let mut result = self.summarize(seed_data, slot_size, &samples);
+        result.convergence = vec![ConvergenceTrace { rounds, converged }];
         result
     }
 
-    pub fn run(&self, seed_data:f64, slot_size:usize) -> BootstrapResult {
+    fn summarize(&self, seed_data:f64, slot_size:usize, samples:&Vec<Vec<f64>>) -> BootstrapResult {
         let slots = slot_size;
         let mut current_data = seed_data;
         let mut generated_data = Vec::new();
         generated_data.push(current_data);
         let mut average_data = Vec::new();
+        let mut sampled_levels = vec![seed_data; samples.len()];
+        let mut level_samples = vec![sampled_levels.clone()];
 
         for selected_slot in 0..slots{
-            let mut slot_choices = Vec::new();
-            for _ in 0..self.iterations{
-                //randomly generate index to select data from
-                let mut rng = rand::thread_rng();
-                let random_index = rng.gen_range(0..self.data.len());
-                let data_slot = self.data[random_index][selected_slot];
-                slot_choices.push(data_slot);
-            }
-            let average = slot_choices.iter().sum::<f64>() / slot_choices.len() as f64;
+            let average = samples.iter().map(|x| x[selected_slot]).sum::<f64>() / samples.len() as f64;
             let average = (average * 100.0).round() / 100.0;
             average_data.push(average);
             current_data = current_data + average;
             current_data = (current_data * 100.0).round() / 100.0;
             generated_data.push(current_data);
+
+            for (level, sample) in sampled_levels.iter_mut().zip(samples.iter()) {
+                *level += sample[selected_slot];
+            }
+            level_samples.push(sampled_levels.clone());
         }
-        let result = BootstrapResult::new(generated_data,average_data);
+        let mut result = BootstrapResult::new(generated_data,average_data);
+        result.bands = self.quantiles.iter().map(|quantile| QuantileBand {
+            quantile: *quantile,
+            values: level_samples.iter().map(|x| (Bootstrap::quantile(x, *quantile) * 100.0).round() / 100.0).collect(),
+        }).collect();
+        result.standard_error = level_samples.iter().map(|x| Bootstrap::standard_error(x)).collect();
+        result.sample_count = samples.len();
+        result.method = self.method;
         result
     }
-}
\ No newline at end of file
+
+    // draws iterations paths of slot_size moves from the rows, every move stays at the step it was generated for
+    fn sample_paths<R: Rng + ?Sized>(&self, slot_size:usize, rng:&mut R) -> Vec<Vec<f64>> {
+        let mut samples = vec![Vec::with_capacity(slot_size); self.iterations];
+        match self.method {
+            BootstrapMethod::PerSlot => {
+                for selected_slot in 0..slot_size{
+                    for sample in samples.iter_mut(){
+                        //randomly generate index to select data from
+                        let random_index = self.draw_row(rng);
+                        sample.push(self.data[random_index][selected_slot]);
+                    }
+                }
+            },
+            BootstrapMethod::WholePath => {
+                for sample in samples.iter_mut(){
+                    let random_index = self.draw_row(rng);
+                    sample.extend_from_slice(&self.data[random_index][..slot_size]);
+                }
+            },
+            BootstrapMethod::MovingBlock { block_length } => {
+                let block_length = block_length.max(1);
+                for sample in samples.iter_mut(){
+                    // the first block is cut at a random length so the blocks do not all end on the same steps
+                    let mut block_end = rng.gen_range(1..=block_length);
+                    let mut random_index = self.draw_row(rng);
+                    for selected_slot in 0..slot_size{
+                        if selected_slot == block_end {
+                            random_index = self.draw_row(rng);
+                            block_end += block_length;
+                        }
+                        sample.push(self.data[random_index][selected_slot]);
+                    }
+                }
+            },
+            BootstrapMethod::Stationary { mean_block_length } => {
+                let switch_probability = 1.0 / mean_block_length.max(1.0);
+                for sample in samples.iter_mut(){
+                    let mut random_index = self.draw_row(rng);
+                    for selected_slot in 0..slot_size{
+                        if selected_slot > 0 && rng.gen_bool(switch_probability) {
+                            random_index = self.draw_row(rng);
+                        }
+                        sample.push(self.data[random_index][selected_slot]);
+                    }
+                }
+            },
+        }
+        samples
+    }
+
+    // running totals of every sampled path, one row per step after the seed
+    fn cumulative_levels(samples:&Vec<Vec<f64>>, slot_size:usize)->Vec<Vec<f64>>{
+        let mut levels = vec![0.0; samples.len()];
+        (0..slot_size).map(|selected_slot| {
+            for (level, sample) in levels.iter_mut().zip(samples.iter()) {
+                *level += sample[selected_slot];
+            }
+            levels.clone()
+        }).collect()
+    }
+
+    fn mean_levels(samples:&Vec<Vec<f64>>, slot_size:usize)->Vec<f64>{
+        Bootstrap::cumulative_levels(samples, slot_size).iter().map(|x| x.iter().sum::<f64>() / x.len() as f64).collect()
+    }
+
+    // linear interpolation between the closest ranks
+    fn quantile(samples:&Vec<f64>, quantile:f64)->f64{
+        if samples.is_empty() {
+            return f64::NAN;
+        }
+        let mut sorted = samples.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let position = quantile.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+        let lower = position.floor() as usize;
+        let upper = position.ceil() as usize;
+        sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+    }
+
+    fn standard_error(samples:&Vec<f64>)->f64{
+        if samples.len() < 2 {
+            return 0.0;
+        }
+        let count = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0);
+        (variance / count).sqrt()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the move of row r at step s is 10 * r + s, so every move tells its row and its step
+    fn rows() -> Vec<Vec<f64>> {
+        (0..5).map(|row| (0..6).map(|step| (10 * row + step) as f64).collect()).collect()
+    }
+
+    fn sample(method: BootstrapMethod, seed: u64) -> Vec<Vec<f64>> {
+        let bootstrap = Bootstrap::new(200, rows()).with_method(method);
+        bootstrap.sample_paths(6, &mut StdRng::seed_from_u64(seed))
+    }
+
+    fn row_of(value: f64) -> usize {
+        (value / 10.0).floor() as usize
+    }
+
+    fn assert_moves_keep_their_step(samples: &Vec<Vec<f64>>) {
+        for sample in samples {
+            assert_eq!(sample.len(), 6);
+            for (step, value) in sample.iter().enumerate() {
+                assert_eq!(*value as usize % 10, step, "{:?}", sample);
+            }
+        }
+    }
+
+    #[test]
+    fn whole_path_returns_whole_rows() {
+        let rows = rows();
+        let samples = sample(BootstrapMethod::WholePath, 1);
+        assert_eq!(samples.len(), 200);
+        assert!(samples.iter().all(|x| rows.contains(x)));
+    }
+
+    #[test]
+    fn moving_blocks_keep_moves_at_their_step() {
+        let samples = sample(BootstrapMethod::MovingBlock { block_length: 2 }, 2);
+        assert_moves_keep_their_step(&samples);
+        // after the first block the rows only change every second step
+        for sample in samples.iter() {
+            let switches: Vec<usize> = (1..6).filter(|x| row_of(sample[*x]) != row_of(sample[x - 1])).collect();
+            assert!(switches.windows(2).all(|x| (x[1] - x[0]) % 2 == 0), "{:?}", sample);
+        }
+        assert!(samples.iter().any(|x| x.iter().any(|value| row_of(*value) != row_of(x[0]))));
+    }
+
+    #[test]
+    fn stationary_blocks_keep_moves_at_their_step() {
+        let samples = sample(BootstrapMethod::Stationary { mean_block_length: 2.0 }, 3);
+        assert_moves_keep_their_step(&samples);
+        assert!(samples.iter().any(|x| x.iter().any(|value| row_of(*value) != row_of(x[0]))));
+
+        // with very long blocks the rows are kept whole
+        let rows = rows();
+        assert!(sample(BootstrapMethod::Stationary { mean_block_length: 1e12 }, 3).iter().all(|x| rows.contains(x)));
+    }
+
+    #[test]
+    fn score_weights_favour_lower_scores() {
+        let weights = ScoreWeighting::Softmax { temperature: 1.0 }.weights(&vec![0.0, 1.0, 2.0, f64::NAN]);
+        let expected = [1.0, (-1.0f64).exp(), (-2.0f64).exp(), 0.0];
+        assert!(weights.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-12), "{:?}", weights);
+
+        // a cooler softmax leans harder on the best score
+        let cool = ScoreWeighting::Softmax { temperature: 0.1 }.weights(&vec![0.0, 1.0]);
+        assert!(cool[1] < weights[1]);
+
+        // ranks count the rows that score worse, ties share a rank
+        assert_eq!(ScoreWeighting::Rank.weights(&vec![3.0, 1.0, 2.0, 1.0]), vec![1.0, 3.0, 2.0, 3.0]);
+        assert_eq!(ScoreWeighting::Rank.weights(&vec![2.0, f64::INFINITY, 1.0]), vec![2.0, 0.0, 3.0]);
+    }
+
+    #[test]
+    fn scores_steer_the_row_draws() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let draws = |bootstrap: &Bootstrap, rng: &mut StdRng| {
+            let mut counts = vec![0; 5];
+            for _ in 0..1000 {
+                counts[bootstrap.draw_row(rng)] += 1;
+            }
+            counts
+        };
+
+        let scores = vec![4.0, 3.0, 2.0, 1.0, 0.0];
+        let softmax = Bootstrap::new(1, rows()).with_scores(&scores, ScoreWeighting::Softmax { temperature: 1.0 });
+        let counts = draws(&softmax, &mut rng);
+        assert!(counts.windows(2).all(|x| x[0] < x[1]), "{:?}", counts);
+
+        let rank = Bootstrap::new(1, rows()).with_scores(&scores, ScoreWeighting::Rank);
+        let counts = draws(&rank, &mut rng);
+        assert!(counts[0] < counts[4], "{:?}", counts);
+
+        // scores that do not line up with the rows are ignored
+        assert!(Bootstrap::new(1, rows()).with_scores(&vec![0.0], ScoreWeighting::Rank).cumulative_weights.is_none());
+    }
+
+    #[test]
+    fn convergence_stops_at_max_rounds_and_traces_every_round() {
+        let bootstrap = Bootstrap::new(50, rows());
+        // a change can never fall below a tolerance of zero
+        let convergence = Convergence { tolerance: 0.0, max_rounds: 4, criterion: ConvergenceCriterion::Estimate };
+        let result = bootstrap.run_until_converged(0.0, 6, &convergence, &mut StdRng::seed_from_u64(5));
+
+        assert_eq!(result.convergence.len(), 1);
+        let trace = &result.convergence[0];
+        assert!(!trace.converged);
+        assert_eq!(trace.rounds.iter().map(|x| x.round).collect::<Vec<usize>>(), vec![1, 2, 3, 4]);
+        assert_eq!(trace.rounds.iter().map(|x| x.sample_count).collect::<Vec<usize>>(), vec![50, 100, 150, 200]);
+        assert!(trace.rounds[0].estimate_change.is_none());
+        assert!(trace.rounds[1..].iter().all(|x| x.estimate_change.is_some()));
+        assert!(trace.rounds.windows(2).all(|x| x[1].standard_error < x[0].standard_error));
+        assert_eq!(result.sample_count, 200);
+    }
+
+    #[test]
+    fn convergence_stops_once_the_estimates_settle() {
+        // identical rows give the same estimate every round
+        let bootstrap = Bootstrap::new(10, vec![vec![1.0, 2.0, 3.0]; 3]);
+        let estimate = Convergence { tolerance: 1e-9, max_rounds: 10, criterion: ConvergenceCriterion::Estimate };
+        let result = bootstrap.run_until_converged(0.0, 3, &estimate, &mut StdRng::seed_from_u64(6));
+        assert!(result.convergence[0].converged);
+        assert_eq!(result.convergence[0].rounds.len(), 2);
+        assert_eq!(result.convergence[0].rounds[1].estimate_change, Some(0.0));
+
+        let standard_error = Convergence { tolerance: 1e-9, max_rounds: 10, criterion: ConvergenceCriterion::StandardError };
+        let result = bootstrap.run_until_converged(0.0, 3, &standard_error, &mut StdRng::seed_from_u64(6));
+        assert!(result.convergence[0].converged);
+        assert_eq!(result.convergence[0].rounds.len(), 1);
+    }
+
+    #[test]
+    fn denormalize_adds_every_move_to_the_seed() {
+        assert_eq!(Bootstrap::denormalize(10.0, vec![1.0, -2.0, 0.5]), vec![10.0, 11.0, 9.0, 9.5]);
+        assert_eq!(Bootstrap::denormalize(10.0, Vec::new()), vec![10.0]);
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Bootstrap;
+
+// rates how far a denormalized path is from the test data, lower is better
+// both are levels starting at the same seed, they are compared over their common length
+pub trait PathMetric {
+    fn error(&self, path: &[f64], test_data: &[f64]) -> f64;
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum PathError {
+    // square root of the summed squared differences
+    Euclidean,
+    Rmse,
+    Mae,
+    // largest difference at any step
+    MaxAbsolute,
+    // difference at the last common step
+    Terminal,
+}
+
+impl Default for PathError {
+    fn default() -> Self {
+        PathError::Euclidean
+    }
+}
+
+// a row of the bootstrap data ranked against the test data, rank 0 is the closest
+// source_index is the position of the row in the data it was ranked from
+#[derive(Debug,Serialize, Deserialize,Clone,PartialEq)]
+pub struct RankedCandidate {
+    pub score: f64,
+    pub path: Vec<f64>,
+    pub rank: usize,
+    pub source_index: usize,
+}
+
+impl PathMetric for PathError {
+    fn error(&self, path: &[f64], test_data: &[f64]) -> f64 {
+        let differences: Vec<f64> = path.iter().zip(test_data.iter()).map(|(a, b)| a - b).collect();
+        if differences.is_empty() {
+            return 0.0;
+        }
+        match self {
+            PathError::Euclidean => differences.iter().map(|x| x.powi(2)).sum::<f64>().sqrt(),
+            PathError::Rmse => (differences.iter().map(|x| x.powi(2)).sum::<f64>() / differences.len() as f64).sqrt(),
+            PathError::Mae => differences.iter().map(|x| x.abs()).sum::<f64>() / differences.len() as f64,
+            PathError::MaxAbsolute => differences.iter().map(|x| x.abs()).fold(0.0, f64::max),
+            PathError::Terminal => differences[differences.len() - 1].abs(),
+        }
+    }
+}
+
+impl Bootstrap {
+    // denormalizes every row from the first test value and ranks the paths by the metric
+    // rows with the same score keep their order, scores that are not numbers rank last
+    pub fn rank_candidates(data: &Vec<Vec<f64>>, test_data: &Vec<f64>, metric: &dyn PathMetric) -> Vec<RankedCandidate> {
+        if test_data.is_empty() {
+            return Vec::new();
+        }
+        let mut candidates: Vec<RankedCandidate> = data.iter().enumerate().map(|(source_index, row)| {
+            let path = Bootstrap::denormalize(test_data[0], row.to_vec());
+            RankedCandidate {
+                score: metric.error(&path, test_data),
+                path,
+                rank: 0,
+                source_index,
+            }
+        }).collect();
+
+        candidates.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or_else(|| a.score.is_nan().cmp(&b.score.is_nan())));
+        for (rank, candidate) in candidates.iter_mut().enumerate() {
+            candidate.rank = rank;
+        }
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_errors() {
+        let (path, test_data) = ([10.0, 11.0, 13.0, 20.0], [10.0, 11.0, 12.0]);
+        assert_eq!(PathError::Euclidean.error(&path, &test_data), 1.0);
+        assert!((PathError::Rmse.error(&path, &test_data) - (1.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert!((PathError::Mae.error(&path, &test_data) - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(PathError::MaxAbsolute.error(&path, &test_data), 1.0);
+        assert_eq!(PathError::Terminal.error(&path, &test_data), 1.0);
+        assert_eq!(PathError::Euclidean.error(&path, &[]), 0.0);
+    }
+
+    #[test]
+    fn ties_keep_their_order_and_nan_ranks_last() {
+        // every row holds moves, its path starts at the first test value and adds them up
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![1.0, f64::NAN, 0.0],
+            vec![1.0, 1.0, 5.0],
+            vec![0.0, 2.0, 0.0],
+            vec![2.0, -1.0, 0.0],
+            vec![0.0, 0.0, 0.0],
+        ];
+        let test_data = vec![10.0, 11.0, 12.0];
+        let ranked = Bootstrap::rank_candidates(&data, &test_data, &PathError::Euclidean);
+
+        let sources: Vec<usize> = ranked.iter().map(|x| x.source_index).collect();
+        assert_eq!(sources, vec![2, 0, 3, 4, 5, 1]);
+        let ranks: Vec<usize> = ranked.iter().map(|x| x.rank).collect();
+        assert_eq!(ranks, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(ranked[0].path, vec![10.0, 11.0, 12.0, 17.0]);
+        assert_eq!(ranked[0].score, 0.0);
+        assert_eq!(ranked[1].path, vec![10.0, 11.0, 11.0, 11.0]);
+        assert_eq!(ranked[1].score, ranked[2].score);
+        assert!(ranked[5].score.is_nan());
+
+        let top = Bootstrap::select_top_results(&data, &test_data, 2, &PathError::Euclidean);
+        assert_eq!(top, ranked[..2].to_vec());
+        assert!(Bootstrap::rank_candidates(&data, &Vec::new(), &PathError::Euclidean).is_empty());
+    }
+}
@@ -1,10 +1,71 @@
-use actix_web::{post, Responder, HttpResponse};
+use actix_web::{post, Responder, HttpResponse, web::{self, Json}};
+use aslan_data::{DataNode, Direction, EdgeWeighting, GraphExport, MarkovAnalysis, MarkovAnalyzer, PruneOptions, PruneReport};
+use log::{info, warn};
 use burn_autodiff::ADBackendDecorator;
 use burn_ndarray::{NdArrayDevice, NdArrayBackend};
+use serde::{Deserialize, Serialize};
 
-use crate::{helpers::dataparser::{tokenizer, generate_test_prediction}, transformer::embedding::{generate_unique_tokens, train_model}, core::training};
+use crate::{helpers::{dataparser::{tokenizer, generate_test_prediction}, random::resolve_seed}, transformer::embedding::{generate_unique_tokens, train_model}, core::training, db::mongodb::MongoClient};
 use crate::core;
 
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Dot,
+    GraphML,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportParameters {
+    pub symbol: String,
+    pub path: String,
+    pub market: String,
+    pub format: ExportFormat,
+    pub top_edges: Option<usize>,
+    #[serde(default)]
+    pub min_weight: f64,
+    // exports the undirected edges as an undirected graph when not set
+    pub direction: Option<Direction>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneParameters {
+    pub symbol: String,
+    pub path: String,
+    pub market: String,
+    // symbol the pruned model is saved under
+    pub name: String,
+    pub options: PruneOptions,
+    #[serde(default)]
+    pub weighting: EdgeWeighting,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneResponse {
+    message: String,
+    report: Option<PruneReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnalysisParameters {
+    pub symbol: String,
+    pub path: String,
+    pub market: String,
+    #[serde(default)]
+    pub direction: Direction,
+    pub absorbing_threshold: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedParameters {
+    pub rng_seed: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnalysisResponse {
+    message: String,
+    analysis: Option<MarkovAnalysis>,
+}
+
 #[post("/model")]
 pub async fn model() -> impl Responder {
     tokio::spawn(async move {
@@ -14,15 +75,20 @@ pub async fn model() -> impl Responder {
 }
 
 // Generate tokens
+// the embeddings are initialized from the rng_seed query parameter, or a random seed when not set
 #[post("/generateTokens")]
-pub async fn generate_tokens() -> impl Responder {
+pub async fn generate_tokens(query: web::Query<SeedParameters>) -> impl Responder {
+    let rng_seed = match resolve_seed(query.rng_seed) {
+        Ok(rng_seed) => rng_seed,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
     tokio::spawn(async move {
         // tokenize the data
         tokenizer().await;
         // generate embeddings based on the tokenized data
-        generate_unique_tokens().await;
+        generate_unique_tokens(rng_seed).await;
     });
-    HttpResponse::Ok().body("Aslan is generating tokens")
+    HttpResponse::Ok().body(format!("Aslan is generating tokens with seed {}", rng_seed))
 }
 
 #[post("/generateTestData")]
@@ -41,4 +107,103 @@ pub async fn burn_generate() -> impl Responder {
     training::run::<ADBackendDecorator<NdArrayBackend<f64>>>(device).await;
     
     HttpResponse::Ok().body("Aslan is training the embeddings")
-}
\ No newline at end of file
+}
+
+// exports a trained model as DOT or GraphML for inspection
+#[post("/exportModel")]
+pub async fn export_model(body: web::Json<ExportParameters>) -> impl Responder {
+    let mongo_client = MongoClient::new().await;
+    if !mongo_client.find_model_entry(body.symbol.clone(), body.path.clone()).await {
+        return HttpResponse::NotFound().body("Model does not exist");
+    }
+    let nodes = mongo_client.load_model(body.symbol.clone(), body.path.clone(), body.market.clone()).await;
+
+    let mut graph_export = GraphExport::new();
+    graph_export.top_edges = body.top_edges;
+    graph_export.min_weight = body.min_weight;
+    graph_export.direction = body.direction;
+
+    match body.format {
+        ExportFormat::Dot => HttpResponse::Ok().content_type("text/vnd.graphviz").body(graph_export.to_dot(&nodes)),
+        ExportFormat::GraphML => HttpResponse::Ok().content_type("application/xml").body(graph_export.to_graphml(&nodes)),
+    }
+}
+
+// prunes a stored model and saves the compacted copy under a new name, replacing a model already saved there
+#[post("/pruneModel")]
+pub async fn prune_model(body: web::Json<PruneParameters>) -> Json<PruneResponse> {
+    if let Err(e) = body.weighting.validate() {
+        return Json(PruneResponse { message: e, report: None });
+    }
+    let mongo_client = MongoClient::new().await;
+    if !mongo_client.find_model_entry(body.symbol.clone(), body.path.clone()).await {
+        let response = PruneResponse {
+            message: "Model does not exist".to_string(),
+            report: None,
+        };
+        return Json(response)
+    }
+    let mut nodes = mongo_client.load_model(body.symbol.clone(), body.path.clone(), body.market.clone()).await;
+
+    let report = DataNode::prune(&mut nodes, &body.options, &body.weighting);
+    info!("Pruned model {}: {:?}", body.symbol, report);
+
+    mongo_client.replace_model(body.name.clone(), nodes, body.path.clone(), body.market.clone()).await;
+    mongo_client.add_model_entry(body.name.clone(), body.path.clone(), body.market.clone()).await;
+
+    let response = PruneResponse {
+        message: "Pruned model saved".to_string(),
+        report: Some(report),
+    };
+    Json(response)
+}
+
+// runs the markov chain analysis of a stored model, saves it and returns it
+#[post("/analyzeModel")]
+pub async fn analyze_model(body: web::Json<AnalysisParameters>) -> Json<AnalysisResponse> {
+    let mongo_client = MongoClient::new().await;
+    if !mongo_client.find_model_entry(body.symbol.clone(), body.path.clone()).await {
+        let response = AnalysisResponse {
+            message: "Model does not exist".to_string(),
+            analysis: None,
+        };
+        return Json(response)
+    }
+    let nodes = mongo_client.load_model(body.symbol.clone(), body.path.clone(), body.market.clone()).await;
+
+    let mut analyzer = MarkovAnalyzer::new();
+    analyzer.direction = body.direction;
+    if let Some(absorbing_threshold) = body.absorbing_threshold {
+        analyzer.absorbing_threshold = absorbing_threshold;
+    }
+    let analysis = analyzer.analyze(&nodes);
+    info!("Analyzed model {}: entropy rate {}, mixing time {:?}", body.symbol, analysis.entropy_rate, analysis.mixing_time);
+    if !analysis.converged {
+        warn!("Stationary distribution of {} did not converge, residual {}", body.symbol, analysis.residual);
+    }
+
+    mongo_client.save_model_analysis(body.symbol.clone(), body.path.clone(), &analysis).await;
+
+    let response = AnalysisResponse {
+        message: "Model analysis saved".to_string(),
+        analysis: Some(analysis),
+    };
+    Json(response)
+}
+
+// returns the last saved analysis of a model
+#[post("/modelAnalysis")]
+pub async fn model_analysis(body: web::Json<AnalysisParameters>) -> Json<AnalysisResponse> {
+    let mongo_client = MongoClient::new().await;
+    let response = match mongo_client.load_model_analysis(body.symbol.clone(), body.path.clone()).await {
+        Some(analysis) => AnalysisResponse {
+            message: "Model analysis found".to_string(),
+            analysis: Some(analysis),
+        },
+        None => AnalysisResponse {
+            message: "Model analysis does not exist".to_string(),
+            analysis: None,
+        },
+    };
+    Json(response)
+}
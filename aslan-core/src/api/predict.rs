@@ -3,26 +3,58 @@ use actix_web::{
     web::{self, Json},
 };
 use apalis::{prelude::{JobContext, Storage,JobResult, Job, JobError}, postgres::PostgresStorage};
-use aslan_bootstrap::BootstrapResult;
-use aslan_data::DataNode;
+use aslan_bootstrap::{Barrier, BootstrapMethod, BootstrapResult, Convergence, EventAnalysis, ScoreWeighting};
+use aslan_data::{DataNode, Direction};
+use aslan_wavereduce::{Constraint, GenerationMode, SamplingStrategy, ScoringMetric, WaveCollapse, WaveReducePool};
 use serde::{Deserialize, Serialize};
 use log::{info, error};
+use std::sync::{Arc, OnceLock};
+use rand::{prelude::*, rngs::StdRng};
 
-use crate::db::mongodb::MongoClient;
+use crate::{db::mongodb::{MongoClient, PredictionPathsEntry}, helpers::random::resolve_seed};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DataResponse {
     message: String,
     data: Option<BootstrapResult>,
+    // seed the prediction was generated with, send it back to repeat the prediction
+    rng_seed: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PredictJob {
+    // id of the prediction the paths are stored under, jobs queued without one do not store their paths
+    #[serde(default)]
+    pub id: String,
     pub symbol: String,
     pub path: String,
     pub market: String,
     pub seed: Vec<f64>,
     pub size: usize,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    #[serde(default)]
+    pub generation: GenerationMode,
+    #[serde(default)]
+    pub sampling: SamplingStrategy,
+    #[serde(default)]
+    pub bootstrap: BootstrapMethod,
+    #[serde(default)]
+    pub candidate_weighting: Option<CandidateWeighting>,
+    #[serde(default)]
+    pub convergence: Option<Convergence>,
+}
+
+// draws the candidate paths in the bootstrap by how closely they follow the seed moves under the metric
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CandidateWeighting {
+    #[serde(default)]
+    pub metric: ScoringMetric,
+    pub weighting: ScoreWeighting,
 }
 
 impl Job for PredictJob {
@@ -38,6 +70,31 @@ pub struct PredictParameters{
     pub path: String,
     pub seed: Vec<f64>,
     pub size: usize,
+    // forward forecasts after the seed, backward backcasts the values before it
+    #[serde(default)]
+    pub direction: Direction,
+    // seed of the prediction rng, a random one is drawn when not set
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    // known facts about the forecast, levels, highs and lows are prices, sums and ranges are price moves
+    // only forward predictions can be constrained
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    // random walks or the most probable paths, ignored when there are constraints
+    #[serde(default)]
+    pub generation: GenerationMode,
+    // temperature, top-k, top-p and repetition penalty of the random walks, only used when sampling
+    #[serde(default)]
+    pub sampling: SamplingStrategy,
+    // how the bootstrap resamples the generated paths, the same rng_seed walks the same paths with any method
+    #[serde(default)]
+    pub bootstrap: BootstrapMethod,
+    // candidates are equally likely in the bootstrap when not set
+    #[serde(default)]
+    pub candidate_weighting: Option<CandidateWeighting>,
+    // keeps bootstrapping in rounds until the estimates settle, a single round when not set
+    #[serde(default)]
+    pub convergence: Option<Convergence>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,15 +102,72 @@ pub struct PredictDataResponse {
     message: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventParameters {
+    pub symbol: String,
+    pub market: String,
+    pub path: String,
+    pub seed: Vec<f64>,
+    // number of steps after the last seed value the paths are simulated for
+    pub size: usize,
+    pub barriers: Vec<Barrier>,
+    // simulated paths, 1000 when not set
+    #[serde(default)]
+    pub paths: Option<usize>,
+    // bins of the terminal value histogram, 20 when not set
+    #[serde(default)]
+    pub bins: Option<usize>,
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    #[serde(default)]
+    pub sampling: SamplingStrategy,
+    #[serde(default)]
+    pub bootstrap: BootstrapMethod,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventResponse {
+    message: String,
+    events: Option<EventAnalysis>,
+    rng_seed: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PredictionPathsParameters {
+    pub id: String,
+    pub market: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PredictionPathsResponse {
+    message: String,
+    // one entry per symbol that contributed to the prediction
+    paths: Vec<PredictionPathsEntry>,
+}
+
 
 #[post("/addPredictionJob")]
 pub async fn add_predict_job(body: web::Json<PredictParameters>, storage: web::Data<PostgresStorage<PredictJob>>) -> Json<PredictDataResponse> {
+    // resolved before queueing so a bad seed is rejected to the caller instead of failing the job
+    let rng_seed = match resolve_seed(body.rng_seed) {
+        Ok(rng_seed) => rng_seed,
+        Err(e) => return Json(PredictDataResponse { message: e }),
+    };
     let new_job = PredictJob {
+        id: body.id.clone(),
         symbol: body.symbol.clone(),
         path: body.path.clone(),
         market: body.market.clone(),
         seed: body.seed.clone(),
         size: body.size,
+        direction: body.direction,
+        rng_seed: Some(rng_seed),
+        constraints: body.constraints.clone(),
+        generation: body.generation,
+        sampling: body.sampling,
+        bootstrap: body.bootstrap,
+        candidate_weighting: body.candidate_weighting.clone(),
+        convergence: body.convergence,
     };
     let storage = &*storage.into_inner();
     let mut storage = storage.clone();
@@ -83,16 +197,26 @@ pub async fn predict_job(job: PredictJob, _ctx: JobContext) -> Result<JobResult,
     //TODO propergate errors up stack to control job result
     //build_model(job.symbol, job.path, job.market).await;
     let params  = PredictParameters{
-        id: "0".to_string(), //TODO: fix this hack",
+        id: job.id.clone(),
         symbol: job.symbol.clone(),
         path: job.path.clone(),
         market: job.market.clone(),
         seed: job.seed.clone(),
         size: job.size,
+        direction: job.direction,
+        rng_seed: job.rng_seed,
+        constraints: job.constraints.clone(),
+        generation: job.generation,
+        sampling: job.sampling,
+        bootstrap: job.bootstrap,
+        candidate_weighting: job.candidate_weighting.clone(),
+        convergence: job.convergence,
     };
 
-    let final_results = generate_results(params).await;
-    info!("Final Results: {:?}", final_results);
+    match generate_results(params).await {
+        Ok((final_results, rng_seed)) => info!("Final Results: {:?} with seed {}", final_results.generated_data, rng_seed),
+        Err(e) => error!("Prediction job failed: {}", e),
+    }
     
     Ok(JobResult::Success)
 }
@@ -106,38 +230,166 @@ pub async fn generate(data: web::Json<PredictParameters>) -> Json<DataResponse>
         market: data.market.clone(),
         seed: data.seed.clone(),
         size: data.size,
+        direction: data.direction,
+        rng_seed: data.rng_seed,
+        constraints: data.constraints.clone(),
+        generation: data.generation,
+        sampling: data.sampling,
+        bootstrap: data.bootstrap,
+        candidate_weighting: data.candidate_weighting.clone(),
+        convergence: data.convergence,
     };
     
-    let final_results = generate_results(params).await;
-    info!("Final Results: {:?}", final_results);
+    let response = match generate_results(params).await {
+        Ok((final_results, rng_seed)) => {
+            info!("Final Results: {:?} with seed {}", final_results.generated_data, rng_seed);
+            DataResponse {
+                message: "Predicted Data".to_string(),
+                data: Some(final_results),
+                rng_seed: Some(rng_seed),
+            }
+        },
+        Err(e) => DataResponse {
+            message: e,
+            data: None,
+            rng_seed: None,
+        },
+    };
+    Json(response)
+}
+
+// returns the candidate paths every symbol generated for a prediction
+#[post("/predictionPaths")]
+pub async fn prediction_paths(body: web::Json<PredictionPathsParameters>) -> Json<PredictionPathsResponse> {
+    let mongodb = MongoClient::new().await;
+    let paths = mongodb.load_prediction_paths(body.id.clone(), body.market.clone()).await;
+    let message = if paths.is_empty() {
+        "Prediction paths do not exist".to_string()
+    } else {
+        "Prediction paths found".to_string()
+    };
+    Json(PredictionPathsResponse { message, paths })
+}
 
-    let response = DataResponse {
-        message: "Predicted Data".to_string(),
-        data: Some(BootstrapResult { generated_data: final_results
-            , average_data: vec![] }),
+// simulates paths of one symbol after the seed and returns how likely and how soon they hit the barriers,
+// along with the distribution of the values they end on
+#[post("/predictEvents")]
+pub async fn predict_events(body: web::Json<EventParameters>) -> Json<EventResponse> {
+    let rng_seed = match resolve_seed(body.rng_seed) {
+        Ok(rng_seed) => rng_seed,
+        Err(e) => return Json(EventResponse { message: e, events: None, rng_seed: None }),
+    };
+    let mongodb = MongoClient::new().await;
+    let response = match simulate_events(&body, rng_seed, &mongodb).await {
+        Ok(events) => EventResponse {
+            message: "Events simulated".to_string(),
+            events: Some(events),
+            rng_seed: Some(rng_seed),
+        },
+        Err(e) => {
+            error!("Event simulation failed: {}", e);
+            EventResponse {
+                message: e,
+                events: None,
+                rng_seed: Some(rng_seed),
+            }
+        },
     };
     Json(response)
 }
 
-pub async fn generate_results( data: PredictParameters)-> Vec<f64>{
+async fn simulate_events(params: &EventParameters, rng_seed: u64, mongodb: &MongoClient) -> Result<EventAnalysis, String> {
+    // the request is checked before the model is looked up, an empty simulation would only return nan
+    if params.seed.len() < 2 {
+        return Err("Seed must be at least 2 entries".to_string());
+    }
+    if params.size < 1 {
+        return Err("Size must be at least 1".to_string());
+    }
+    if params.paths == Some(0) {
+        return Err("Paths must be at least 1".to_string());
+    }
+    if params.bins == Some(0) {
+        return Err("Bins must be at least 1".to_string());
+    }
+    if !mongodb.find_model_entry(params.symbol.clone(), params.path.clone()).await {
+        return Err("Model does not exist".to_string());
+    }
+    let model = mongodb.load_model(params.symbol.clone(), params.path.clone(), params.market.clone()).await;
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+
+    // the candidates are walked the same way as for a forward prediction
+    let predection_parameters = convert_seed(params.seed.clone());
+    let wavereduce = aslan_wavereduce::WaveReduce::new(predection_parameters[predection_parameters.len()-1], params.size, 100 * predection_parameters.len())
+        .with_history(predection_parameters.clone())
+        .with_sampling(params.sampling);
+    let (size, paths, bins, method) = (params.size, params.paths.unwrap_or(1000), params.bins.unwrap_or(20), params.bootstrap);
+    let start = params.seed[params.seed.len()-1];
+    let barriers = params.barriers.clone();
+    let pool = wavereduce_pool();
+
+    // the walks and the simulation are cpu bound, so they run on a blocking thread
+    tokio::task::spawn_blocking(move || {
+        let wavereduce_results = pool.generate_results(wavereduce, &model, rng.gen());
+        let result_space: Vec<Vec<f64>> = aslan_wavereduce::WaveReduceSolution::flatten_results(&wavereduce_results.results)
+            .into_iter()
+            .filter(|x| x.len() >= size)
+            .collect();
+        if result_space.is_empty() {
+            return Err("No paths were generated".to_string());
+        }
+
+        let open_bootstrap = aslan_bootstrap::Bootstrap::new(paths, result_space).with_method(method);
+        Ok(open_bootstrap.simulate_events(start, size, &barriers, bins, &mut rng))
+    }).await.map_err(|e| format!("Event simulation failed: {}", e))?
+}
+
+static WAVEREDUCE_POOL: OnceLock<Arc<WaveReducePool>> = OnceLock::new();
+
+// one pool for the whole process, sized by WAVEREDUCE_THREADS or one thread per cpu
+fn wavereduce_pool() -> Arc<WaveReducePool> {
+    WAVEREDUCE_POOL.get_or_init(|| {
+        let threads = std::env::var("WAVEREDUCE_THREADS").ok().and_then(|x| x.parse::<usize>().ok()).unwrap_or(0);
+        Arc::new(WaveReducePool::new(threads))
+    }).clone()
+}
+
+// returns the averaged prediction and the seed it was generated with
+pub async fn generate_results( data: PredictParameters)-> Result<(BootstrapResult, u64), String>{
+    let rng_seed = resolve_seed(data.rng_seed)?;
     let mongodb = MongoClient::new().await;
-    
 
-    let symbols = mongodb.get_symbols(data.market.clone()).await;
+    let mut symbols = mongodb.get_symbols(data.market.clone()).await;
     let mut predictions = Vec::new();
     let mut tasks = Vec::new();
  
+    // every symbol gets its own stream drawn in a fixed order, the predictions are averaged in the same order
+    symbols.sort();
+    let mut seed_rng = StdRng::seed_from_u64(rng_seed);
+
+    // one pool shared by every symbol
+    let pool = wavereduce_pool();
 
     for symbol in symbols {
         let symbol = symbol.clone();
+        let symbol_seed = seed_rng.gen();
         let path = data.path.clone();
         let market = data.market.clone();
         let size = data.size;
         let seed = data.seed.clone();
+        let direction = data.direction;
+        let constraints = data.constraints.clone();
+        let id = data.id.clone();
+        let generation = data.generation;
+        let sampling = data.sampling;
+        let bootstrap = data.bootstrap;
+        let candidate_weighting = data.candidate_weighting.clone();
+        let convergence = data.convergence;
         let mongodb = mongodb.clone();
+        let pool = pool.clone();
         let task = tokio::spawn( async move  {
         
-            let prediction = predict(symbol, path, market, size, seed, &mongodb).await;
+            let prediction = predict(id, symbol, path, market, size, seed, direction, &constraints, generation, sampling, bootstrap, &candidate_weighting, convergence, symbol_seed, pool, &mongodb).await;
             return prediction;
         });
         tasks.push(task);
@@ -148,7 +400,7 @@ pub async fn generate_results( data: PredictParameters)-> Vec<f64>{
         match prediction {
             Ok(prediction) => {
                 info!("Prediction Generated");
-                predictions.push(prediction.generated_data);
+                predictions.push(prediction);
             },
             Err(e) => {
                 info!("Prediction Failed: {}", e);
@@ -156,25 +408,14 @@ pub async fn generate_results( data: PredictParameters)-> Vec<f64>{
             }
         }
     }
-    let mut final_results = Vec::new();
-
-    let prediction_size = predictions[0].len();
-    
-    for i in 0..prediction_size{
-        let mut sum = 0.0;
-        for prediction in &predictions{
-            sum += prediction[i];
-        }
-        //change result to 2 decimal places
-        let entry = (sum/predictions.len() as f64 * 100.0).round() / 100.0;
-        final_results.push(entry);
-    }
-    return final_results;
+    // the symbols are averaged step by step, bands included
+    let final_results = BootstrapResult::combine(&predictions);
+    return Ok((final_results, rng_seed));
 
 }
 
 // gets a model based on a symbol and generates a prediction
-pub async fn predict(symbol: String, path: String, market: String, size: usize,seed: Vec<f64>, mongodb: &MongoClient) -> Result<BootstrapResult,String> {
+pub async fn predict(id: String, symbol: String, path: String, market: String, size: usize,seed: Vec<f64>, direction: Direction, constraints: &Vec<Constraint>, generation: GenerationMode, sampling: SamplingStrategy, bootstrap: BootstrapMethod, candidate_weighting: &Option<CandidateWeighting>, convergence: Option<Convergence>, rng_seed: u64, pool: Arc<WaveReducePool>, mongodb: &MongoClient) -> Result<BootstrapResult,String> {
 
     // check if model exists in models metadata database
     if !mongodb.find_model_entry(symbol.clone(),path.clone()).await{
@@ -191,51 +432,117 @@ pub async fn predict(symbol: String, path: String, market: String, size: usize,s
     if seed.len() < 2{
         return Err("Seed must be at least 2 entries".to_string());
     }
+    if !constraints.is_empty() && direction == Direction::Backward {
+        return Err("Constraints are only supported for forward predictions".to_string());
+    }
     let predection_parameters =  convert_seed(seed.clone());
-
-    // find all the nodes contain the differences
-    for (index, parameter) in predection_parameters.iter().enumerate(){
-        
-        let partition_size = predection_parameters.len() + size - (index+1);
-        // make this a parameter in the future
-        let wave_result_size = 100;
-
-        let wavereduce = aslan_wavereduce::WaveReduce::new(*parameter, partition_size,wave_result_size);
-        let wavereduce_results = wavereduce.generate_results(&model);
-
-        //print a random result from the wavereduce results
-        let flat_results = aslan_wavereduce::WaveReduceSolution::flatten_results(&wavereduce_results.results);
-        for result in flat_results{
-            let (_, right) = result.split_at(result.len() - size);
-            result_space.push(right.to_vec());
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    // constraint levels are measured from the last seed value, where the forecast starts
+    let constraints: Vec<Constraint> = constraints.iter().map(|x| x.shifted(seed[seed.len()-1])).collect();
+
+    // every walk starts from a node consistent with the whole seed, forward walks continue
+    // from the last difference and backward walks step back from the first one
+    let parameter = match direction {
+        Direction::Forward => predection_parameters[predection_parameters.len()-1],
+        Direction::Backward => predection_parameters[0],
+    };
+    // make this a parameter in the future
+    let wave_result_size = 100 * predection_parameters.len();
+
+    // the walks are cpu bound, so they run on a blocking thread and leave the async workers free
+    let history = predection_parameters.clone();
+    let (settings, wavereduce_results, mut rng) = tokio::task::spawn_blocking(move || {
+        let (settings, results) = if constraints.is_empty() {
+            let wavereduce = aslan_wavereduce::WaveReduce::new(parameter, size, wave_result_size)
+                .with_direction(direction)
+                .with_history(history)
+                .with_sampling(sampling);
+            let settings = wavereduce.clone();
+            let results = match generation {
+                GenerationMode::Sample => pool.generate_results(wavereduce, &model, rng.gen()),
+                GenerationMode::BeamSearch { beam_width, length_penalty } => wavereduce.beam_search(&model, beam_width, length_penalty),
+            };
+            (Some(settings), results)
+        } else {
+            let wavecollapse = WaveCollapse::new(parameter, size, wave_result_size, constraints).with_history(history);
+            (None, wavecollapse.generate_results(&model, &mut rng))
+        };
+        (settings, results, rng)
+    }).await.map_err(|e| format!("Wavereduce failed: {}", e))?;
+
+    // the summaries come in the order of the results, so every score follows its path through the filter
+    let summary = match candidate_weighting {
+        Some(candidate_weighting) => wavereduce_results.get_result_summary(&predection_parameters, &candidate_weighting.metric),
+        None => Vec::new(),
+    };
+    let mut scores = Vec::new();
+    let flat_results = aslan_wavereduce::WaveReduceSolution::flatten_results(&wavereduce_results.results);
+    for (index, result) in flat_results.into_iter().enumerate(){
+        // walks and beam search stop early at a node without edges
+        if result.len() < size {
+            continue;
         }
+        if let Some(summary_item) = summary.get(index) {
+            scores.push(summary_item.score);
+        }
+        result_space.push(result);
+    }
+    // keep the candidate paths behind the prediction so they can be looked up by its id
+    // a failed save is logged, the prediction itself does not depend on it
+    if id.is_empty() {
+        info!("Prediction has no id, paths for {} are not stored", symbol);
+    } else if let Err(e) = mongodb.save_prediction_paths(id, symbol.clone(), market.clone(), path.clone(), settings, wavereduce_results, summary).await {
+        error!("{}", e);
+    }
+
+    if result_space.is_empty() {
+        return Err("No generated path satisfies the constraints".to_string());
     }
 
     let boostrap_iterations = 100;
-    let open_bootstrap = aslan_bootstrap::Bootstrap::new(boostrap_iterations,result_space);
-    let bootstrap_results = open_bootstrap.run(seed[seed.len()-1], size);
+    let (bootstrap_space, start) = match direction {
+        Direction::Forward => (result_space, seed[seed.len()-1]),
+        // step back from the first seed value by negating the reversed differences
+        Direction::Backward => (result_space.iter().map(|row| row.iter().rev().map(|x| -x).collect()).collect(), seed[0]),
+    };
+    let mut open_bootstrap = aslan_bootstrap::Bootstrap::new(boostrap_iterations,bootstrap_space).with_method(bootstrap);
+    if let Some(candidate_weighting) = candidate_weighting {
+        open_bootstrap = open_bootstrap.with_scores(&scores, candidate_weighting.weighting);
+    }
+    let mut bootstrap_results = match convergence {
+        Some(convergence) => open_bootstrap.run_until_converged(start, size, &convergence, &mut rng),
+        None => open_bootstrap.run(start, size, &mut rng),
+    };
+
+    if direction == Direction::Backward {
+        // restore chronological order
+        bootstrap_results.reverse();
+        bootstrap_results.average_data = bootstrap_results.average_data.iter().rev().map(|x| -x).collect();
+    }
     Ok(bootstrap_results)
     //
 }
 
-pub fn generate_prediction(nodes: &Vec<DataNode>, partition_seed:f64,partition_size:usize)->BootstrapResult{
+// none when no walk reaches the full partition size
+pub fn generate_prediction<R: Rng + ?Sized>(nodes: &Vec<DataNode>, partition_seed:f64,partition_size:usize, rng: &mut R)->Option<BootstrapResult>{
     //generate wavereduce results
     info!("Running Wavereduce");
     let waveresultsize = 100;
-    let wavereduce = aslan_wavereduce::WaveReduce::new(partition_seed, partition_size,waveresultsize);
-    let wavereduce_results = wavereduce.generate_results(nodes);
+    // twice the candidates, as walks that reach a node without edges are cut short and left out
+    let wavereduce = aslan_wavereduce::WaveReduce::new(partition_seed, partition_size, 2 * waveresultsize);
+    let flat_results = wavereduce.paths(nodes, rng).accepted(waveresultsize, |path| path.len() >= partition_size);
+    if flat_results.is_empty() {
+        return None;
+    }
 
     info!("Running Boostrap");
 
-    //flatten the top results
-    let flat_results = aslan_wavereduce::WaveReduceSolution::flatten_results(&wavereduce_results.results);
-
     //generate bootstrap results
     let boostrap_iterations = 100;
     let open_bootstrap = aslan_bootstrap::Bootstrap::new(boostrap_iterations,flat_results);
-    let bootstrap_results = open_bootstrap.run(partition_seed, partition_size);
+    let bootstrap_results = open_bootstrap.run(partition_seed, partition_size, rng);
 
-    bootstrap_results
+    Some(bootstrap_results)
 }
 
 // convert seed array into  differences
@@ -3,10 +3,13 @@ use actix_web::{
     web::{self, Json},
 };
 use apalis::{postgres::PostgresStorage, prelude::Storage};
+use aslan_aco::AntColony;
+use aslan_tsp::RouteOptimizer;
+use aslan_data::{DistanceScoring, EdgeWeighting, MergeWeighting};
 use serde::{Deserialize, Serialize};
 use log::{info,error};
 
-use crate::{types::app_state::{TrainJob,Status}, db::mongodb::MongoClient};
+use crate::{types::app_state::{TrainJob,Status,build_composite_model,refine_model,route_model}, db::mongodb::MongoClient, helpers::random::resolve_seed};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct DataRequest {
@@ -25,17 +28,132 @@ struct DataRange {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DataResponse {
     message: String,
+    // seed the task runs with, send it back to repeat the run
+    rng_seed: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DataFileParam {
     symbol: String,
     market: String,
-    path: String
+    path: String,
+    #[serde(default)]
+    scoring: DistanceScoring,
+    #[serde(default)]
+    weighting: EdgeWeighting,
+    #[serde(default)]
+    rng_seed: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompositeParam {
+    name: String,
+    // every symbol of the market when empty
+    #[serde(default)]
+    symbols: Vec<String>,
+    market: String,
+    path: String,
+    #[serde(default)]
+    merge_weighting: MergeWeighting,
+    #[serde(default)]
+    weighting: EdgeWeighting,
+    // only used to backtest the models for accuracy weighting
+    #[serde(default)]
+    rng_seed: Option<u64>,
+}
+
+#[post("/compositeModel")]
+pub async fn composite(body: web::Json<CompositeParam>) -> Json<DataResponse> {
+    let body = body.into_inner();
+    info!("Composing model: {}", body.name);
+    if let Err(e) = body.weighting.validate() {
+        return Json(DataResponse { message: e, rng_seed: None });
+    }
+    let rng_seed = match resolve_seed(body.rng_seed) {
+        Ok(rng_seed) => rng_seed,
+        Err(e) => return Json(DataResponse { message: e, rng_seed: None }),
+    };
+    tokio::spawn(async move {
+        build_composite_model(body.name, body.symbols, body.path, body.market, body.merge_weighting, body.weighting, rng_seed).await;
+    });
+    let response = DataResponse {
+        message: "Aslan is composing the model".to_string(),
+        rng_seed: Some(rng_seed),
+    };
+    Json(response)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefineParam {
+    symbol: String,
+    // symbol the refined model is saved under
+    name: String,
+    market: String,
+    path: String,
+    #[serde(default)]
+    colony: AntColony,
+    #[serde(default)]
+    weighting: EdgeWeighting,
+    #[serde(default)]
+    rng_seed: Option<u64>,
+}
+
+#[post("/refineModel")]
+pub async fn refine(body: web::Json<RefineParam>) -> Json<DataResponse> {
+    let body = body.into_inner();
+    info!("Refining model: {}", body.symbol);
+    if let Err(e) = body.weighting.validate() {
+        return Json(DataResponse { message: e, rng_seed: None });
+    }
+    let rng_seed = match resolve_seed(body.rng_seed) {
+        Ok(rng_seed) => rng_seed,
+        Err(e) => return Json(DataResponse { message: e, rng_seed: None }),
+    };
+    tokio::spawn(async move {
+        refine_model(body.symbol, body.name, body.path, body.market, body.colony, body.weighting, rng_seed).await;
+    });
+    let response = DataResponse {
+        message: "Aslan is refining the model".to_string(),
+        rng_seed: Some(rng_seed),
+    };
+    Json(response)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RouteParam {
+    symbol: String,
+    // symbol the routed model is saved under
+    name: String,
+    market: String,
+    path: String,
+    #[serde(default)]
+    optimizer: RouteOptimizer,
+    #[serde(default)]
+    weighting: EdgeWeighting,
+}
+
+#[post("/routeModel")]
+pub async fn route(body: web::Json<RouteParam>) -> Json<DataResponse> {
+    let body = body.into_inner();
+    info!("Routing model: {}", body.symbol);
+    if let Err(e) = body.weighting.validate() {
+        return Json(DataResponse { message: e, rng_seed: None });
+    }
+    tokio::spawn(async move {
+        route_model(body.symbol, body.name, body.path, body.market, body.optimizer, body.weighting).await;
+    });
+    let response = DataResponse {
+        message: "Aslan is routing the model".to_string(),
+        rng_seed: None,
+    };
+    Json(response)
 }
 
 #[post("/data")]
 pub async fn init(body: web::Json<DataFileParam>, storage: web::Data<PostgresStorage<TrainJob>>) -> Json<DataResponse> {
+    if let Err(e) = body.scoring.validate().and_then(|_| body.weighting.validate()) {
+        return Json(DataResponse { message: e, rng_seed: None });
+    }
 
     let mongo_client = MongoClient::new().await;
     let entry = mongo_client.get_model_metadata(body.symbol.clone(),body.path.clone()).await;
@@ -43,17 +161,25 @@ pub async fn init(body: web::Json<DataFileParam>, storage: web::Data<PostgresSto
         Some(_) => {
             let response = DataResponse {
                 message: "Model already exists".to_string(),
+                rng_seed: None,
             };
             return Json(response)
         },
         None => info!("Model does not exist, creating new model"),
     }
 
+    let rng_seed = match resolve_seed(body.rng_seed) {
+        Ok(rng_seed) => rng_seed,
+        Err(e) => return Json(DataResponse { message: e, rng_seed: None }),
+    };
     let new_job = TrainJob {
         symbol: body.symbol.clone(),
         path: body.path.clone(),
         market: body.market.clone(),
         status: Status::Pending,
+        scoring: body.scoring,
+        weighting: body.weighting,
+        rng_seed: Some(rng_seed),
     };
     let storage = &*storage.into_inner();
     let mut storage = storage.clone();
@@ -65,6 +191,7 @@ pub async fn init(body: web::Json<DataFileParam>, storage: web::Data<PostgresSto
             info!("Model request added to queue");
             let response = DataResponse {
                 message: "Data file added to queue".to_string(),
+                rng_seed: Some(rng_seed),
             };
             Json(response)
         },
@@ -72,6 +199,7 @@ pub async fn init(body: web::Json<DataFileParam>, storage: web::Data<PostgresSto
             error!("Error adding job to queue: {}", e);
             let response = DataResponse {
                 message: format!("Error adding data file to queue: {}", e),
+                rng_seed: None,
             };
             Json(response)
         },
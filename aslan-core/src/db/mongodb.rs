@@ -1,10 +1,13 @@
-use aslan_data::DataNode;
+use aslan_aco::ColonyResult;
+use aslan_bootstrap::{BootstrapResult, QuantileBand};
+use aslan_data::{DataNode, Edge, MarkovAnalysis};
+use aslan_wavereduce::{WaveReduce, WaveReduceSolution, WaveReduceSummary};
 use chrono::{DateTime, Utc};
 use futures::{stream::TryStreamExt, StreamExt};
 use log::{error, info, warn};
 use mongodb::{
     bson::{doc, Document, self,oid::ObjectId, Bson},
-    options::{ClientOptions, FindOptions, ResolverConfig, FindOneOptions},
+    options::{ClientOptions, FindOptions, ResolverConfig, FindOneOptions, ReplaceOptions},
     Client,
 };
 use serde::{Deserialize, Serialize};
@@ -32,8 +35,40 @@ pub struct PredictEntry {
     pub market: String,
     pub path: String,
     pub prediction: Vec<f64>,
+    // at most helpers::random::MAX_SEED, larger values do not fit a bson integer
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    // uncertainty of the prediction, lined up with it like on the /predict response
+    #[serde(default)]
+    pub bands: Vec<QuantileBand>,
+    #[serde(default)]
+    pub standard_error: Vec<f64>,
+    #[serde(default)]
+    pub sample_count: usize,
 }
 
+// the candidate paths one symbol contributed to a prediction
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PredictionPathsEntry {
+    pub _id: String,
+    pub id: String,
+    pub symbol: String,
+    pub path: String,
+    // settings the paths were walked with, none when they were collapsed under constraints
+    pub wavereduce: Option<WaveReduce>,
+    // the first paths that fit in MAX_STORED_STATES
+    pub solution: WaveReduceSolution,
+    // scores of the stored paths against the seed moves, empty when the candidates were not weighted
+    #[serde(default)]
+    pub summary: Vec<WaveReduceSummary>,
+    // paths generated for the prediction, more than the solution holds when it was capped
+    #[serde(default)]
+    pub total_paths: usize,
+}
+
+// keeps a prediction paths document well below the 16mb mongodb limit
+const MAX_STORED_STATES: usize = 200_000;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SymbolData {
     pub symbol: String,
@@ -108,15 +143,9 @@ impl MongoClient {
         let now: DateTime<Utc> = Utc::now();
         let mut nodes = Vec::new();
         for node in data {
-            let mut edges = Vec::new();
-            for edge in node.edges {
-                let edge = doc! { 
-                    "score": edge.score,
-                    "weight": edge.weight,
-                    "value": edge.value,
-                 };
-                edges.push(edge);
-            };
+            let edges = MongoClient::edge_documents(node.edges);
+            let forward_edges = MongoClient::edge_documents(node.forward_edges);
+            let backward_edges = MongoClient::edge_documents(node.backward_edges);
             
             let node = doc! { 
                 "symbol": &symbol,
@@ -124,7 +153,9 @@ impl MongoClient {
                 "timestamp": now.to_rfc3339(),
                 "average": node.average,
                 "members" : node.members,
-                "edges" : edges
+                "edges" : edges,
+                "forward_edges" : forward_edges,
+                "backward_edges" : backward_edges
              };
             nodes.push(node);
         }
@@ -132,6 +163,27 @@ impl MongoClient {
         collection.insert_many(nodes, None).await.unwrap();
     }
 
+    // overwrites a model instead of adding to it, so saving under the same name twice keeps one copy
+    pub async fn replace_model(&self, symbol: String, data: Vec<DataNode>, label: String, market: String) {
+        let database = self.client.database("aslan-model");
+        let collection_name = format!("{}_{}_{}_MODEL", symbol,label,market);
+        database.collection::<Document>(&collection_name).drop(None).await.unwrap();
+        self.export_data(symbol, data, label, market).await;
+    }
+
+    fn edge_documents(data: Vec<Edge>) -> Vec<Document> {
+        let mut edges = Vec::new();
+        for edge in data {
+            let edge = doc! { 
+                "score": edge.score,
+                "weight": edge.weight,
+                "value": edge.value,
+             };
+            edges.push(edge);
+        };
+        edges
+    }
+
     pub async fn save_loss_breakdown (&self, symbol: String, loss_breakdown: LossBreakdown, label: String){
         let database = self.client.database("aslan-meta");
         let collection_name = format!("LOSS_BREAKDOWN");
@@ -160,6 +212,60 @@ impl MongoClient {
         collection.insert_one(loss_breakdown, None).await.unwrap();
     }
 
+    // keeps only the latest trace of every symbol and label
+    pub async fn save_colony_trace(&self, symbol: String, label: String, result: &ColonyResult){
+        let database = self.client.database("aslan-meta");
+        let collection = database.collection("COLONY_TRACE");
+        let now: DateTime<Utc> = Utc::now();
+        let mut iterations = Vec::new();
+        for iteration in result.trace.iter() {
+            let iteration = doc! {
+                "iteration": iteration.iteration as i32,
+                "best_error": iteration.best_error,
+                "mean_error": iteration.mean_error,
+             };
+            iterations.push(iteration);
+        };
+        let id = format!("{}_{}", symbol, label);
+        let trace = doc! {
+            "_id": &id,
+            "symbol": &symbol,
+            "label": &label,
+            "timestamp": now.to_rfc3339(),
+            "best_error": result.best_error,
+            "converged": result.converged,
+            "iterations" : iterations
+         };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection.replace_one(doc! { "_id": &id }, trace, options).await.unwrap();
+    }
+
+    // keeps only the latest analysis of every model
+    pub async fn save_model_analysis(&self, symbol: String, path: String, analysis: &MarkovAnalysis){
+        let database = self.client.database("aslan-meta");
+        let collection = database.collection::<Document>("MODEL_ANALYSIS");
+        let now: DateTime<Utc> = Utc::now();
+        let id = format!("{}_{}", symbol, path);
+        let entry = doc! {
+            "_id": &id,
+            "symbol": &symbol,
+            "path": &path,
+            "timestamp": now.to_rfc3339(),
+            "analysis": bson::to_bson(analysis).unwrap(),
+         };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection.replace_one(doc! { "_id": &id }, entry, options).await.unwrap();
+    }
+
+    pub async fn load_model_analysis(&self, symbol: String, path: String) -> Option<MarkovAnalysis> {
+        let database = self.client.database("aslan-meta");
+        let collection = database.collection::<Document>("MODEL_ANALYSIS");
+        let filter = doc! { "_id": format!("{}_{}", symbol, path) };
+        let entry = collection.find_one(filter, None).await.unwrap()?;
+        let analysis = entry.get_document("analysis").ok()?;
+        bson::from_document::<MarkovAnalysis>(analysis.clone()).ok()
+    }
+
     pub async fn get_node_data(&self, symbol: &str) -> Vec<DataNode> {
         return Vec::new();
     }
@@ -189,7 +295,8 @@ impl MongoClient {
             symbol: symbol,
             path: path,
         };
-        collection.insert_one(entry, None).await.unwrap();
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection.replace_one(doc! { "_id": &entry._id }, entry, options).await.unwrap();
     }
 
     // function to find if a model entry exists
@@ -244,6 +351,8 @@ impl MongoClient {
         while let Some(data) = cursor.try_next().await.unwrap() {
             nodes.push(data);
         }
+        // walks draw their edges from the alias tables, build them once here instead of during the first walks
+        DataNode::build_samplers(&nodes);
         return nodes;
     }
 
@@ -261,7 +370,7 @@ impl MongoClient {
         return symbols;
     }
 
-    pub async fn insert_prediction(&self, id: String, symbol: String, market: String, path: String, prediction: Vec<f64>) {
+    pub async fn insert_prediction(&self, id: String, symbol: String, market: String, path: String, prediction: BootstrapResult, rng_seed: u64) {
         let database = self.client.database("aslan-predictions");
         let collection_name = format!("predictions_{}", market);
         let collection = database.collection::<PredictEntry>(collection_name.as_str());
@@ -270,11 +379,64 @@ impl MongoClient {
             symbol: symbol,
             market: market,
             path: path,
-            prediction: prediction,
+            prediction: prediction.generated_data,
+            rng_seed: Some(rng_seed),
+            bands: prediction.bands,
+            standard_error: prediction.standard_error,
+            sample_count: prediction.sample_count,
         };
         collection.insert_one(entry, None).await.unwrap();
     }
 
+    // replaces the paths of a symbol when a prediction id is reused
+    // the paths are stored in the order they were generated until MAX_STORED_STATES is reached
+    pub async fn save_prediction_paths(&self, id: String, symbol: String, market: String, path: String, wavereduce: Option<WaveReduce>, mut solution: WaveReduceSolution, mut summary: Vec<WaveReduceSummary>) -> Result<(), String> {
+        let database = self.client.database("aslan-predictions");
+        let collection_name = format!("prediction_paths_{}", market);
+        let collection = database.collection::<PredictionPathsEntry>(collection_name.as_str());
+
+        let total_paths = solution.results.len();
+        let mut states = 0;
+        let stored_paths = solution.results.iter().take_while(|x| {
+            states += x.result.len();
+            states <= MAX_STORED_STATES
+        }).count();
+        solution.results.truncate(stored_paths);
+        summary.retain(|x| x.solution_index < stored_paths);
+        if stored_paths < total_paths {
+            warn!("Storing {} of {} paths for prediction {} of {}", stored_paths, total_paths, id, symbol);
+        }
+
+        let entry = PredictionPathsEntry {
+            _id: format!("{}_{}", id, symbol),
+            id: id,
+            symbol: symbol,
+            path: path,
+            wavereduce: wavereduce,
+            solution: solution,
+            summary: summary,
+            total_paths: total_paths,
+        };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        match collection.replace_one(doc! { "_id": &entry._id }, entry, options).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Prediction paths not saved: {}", e)),
+        }
+    }
+
+    pub async fn load_prediction_paths(&self, id: String, market: String) -> Vec<PredictionPathsEntry> {
+        let database = self.client.database("aslan-predictions");
+        let collection_name = format!("prediction_paths_{}", market);
+        let collection = database.collection::<PredictionPathsEntry>(collection_name.as_str());
+
+        let mut cursor = collection.find(doc! { "id": &id }, None).await.unwrap();
+        let mut entries = Vec::new();
+        while let Some(entry) = cursor.try_next().await.unwrap() {
+            entries.push(entry);
+        }
+        entries
+    }
+
     // function to list all collections in mongodb
     pub async fn list_collections(&self, database_name: String) -> Vec<String> {
         let database = self.client.database(database_name.as_str());
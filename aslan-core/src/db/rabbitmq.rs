@@ -1,8 +1,9 @@
 use lapin::{ConnectionProperties, Connection, options::{QueueDeclareOptions, BasicConsumeOptions, BasicPublishOptions, BasicAckOptions}, types::FieldTable, BasicProperties, Channel, message::DeliveryResult};
 use log::{info, error};
 use serde::{Serialize, Deserialize};
+use aslan_data::{DistanceScoring, EdgeWeighting};
 
-use crate::{types::app_state, api::predict, db::mongodb::MongoClient};
+use crate::{types::app_state, api::predict, db::mongodb::MongoClient, helpers::random::resolve_seed};
 
 pub struct RabbitMQ{
     pub channel: Channel,
@@ -14,7 +15,13 @@ pub struct RabbitMQ{
 pub struct ModelParameter {
     symbol: String,
     market: String,
-    path: String
+    path: String,
+    #[serde(default)]
+    scoring: DistanceScoring,
+    #[serde(default)]
+    weighting: EdgeWeighting,
+    #[serde(default)]
+    rng_seed: Option<u64>,
 }
 
 
@@ -89,8 +96,13 @@ impl RabbitMQ {
         let model_parameter: ModelParameter = serde_json::from_slice(&delivery.data).unwrap();
         info!("Received message for model consumer: {}", model_parameter.symbol);
         info!("Building Model");
-        app_state::build_model(model_parameter.symbol, model_parameter.path, model_parameter.market).await;
-        info!("Model Built");
+        match model_parameter.scoring.validate().and_then(|_| model_parameter.weighting.validate()).and_then(|_| resolve_seed(model_parameter.rng_seed)) {
+            Ok(rng_seed) => {
+                app_state::build_model(model_parameter.symbol, model_parameter.path, model_parameter.market, model_parameter.scoring, model_parameter.weighting, rng_seed).await;
+                info!("Model Built with seed {}", rng_seed);
+            },
+            Err(e) => error!("Model not built: {}", e),
+        }
 
         delivery
             .ack(BasicAckOptions::default())
@@ -140,11 +152,17 @@ impl RabbitMQ {
     let path = predict_parameter.path.clone();
 
     //TODO FIX error behavior
-    let prediction = predict::generate_results(predict_parameter).await;
+    let (prediction, rng_seed) = match predict::generate_results(predict_parameter).await {
+        Ok(results) => results,
+        Err(e) => {
+            error!("Prediction not generated: {}", e);
+            return;
+        }
+    };
 
     info!("Saving Prediction to MongoDB");
     let mongodb = MongoClient::new().await;
-    mongodb.insert_prediction(id, symbol, market, path, prediction).await;
+    mongodb.insert_prediction(id, symbol, market, path, prediction, rng_seed).await;
     info!("Saving Prediction to MongoDB completed");
 });
 
@@ -1 +1,2 @@
-pub mod dataparser;
\ No newline at end of file
+pub mod dataparser;
+pub mod random;
\ No newline at end of file
@@ -0,0 +1,15 @@
+use rand::Rng;
+
+// seeds are stored in mongodb, which only holds signed 64 bit integers
+pub const MAX_SEED: u64 = i64::MAX as u64;
+
+// uses the requested seed or draws a fresh one
+// the seed is always handed back to the caller so the run can be repeated exactly
+// requested seeds above MAX_SEED are rejected rather than changed, so a seed never stands for another run
+pub fn resolve_seed(seed: Option<u64>) -> Result<u64, String> {
+    match seed {
+        Some(seed) if seed > MAX_SEED => Err(format!("rng_seed must be at most {}", MAX_SEED)),
+        Some(seed) => Ok(seed),
+        None => Ok(rand::thread_rng().gen_range(0..=MAX_SEED)),
+    }
+}
@@ -1,9 +1,9 @@
 use actix_web::{Responder, HttpResponse};
 use actix_web::{web, App, HttpServer, middleware::Logger};
 mod api;
-use api::model::{model,generate_tokens,generate_test_data,burn_generate};
-use api::task::{init};
-use api::predict::{generate, add_predict_job};
+use api::model::{model,generate_tokens,generate_test_data,burn_generate,export_model,prune_model,analyze_model,model_analysis};
+use api::task::{init,composite,refine,route};
+use api::predict::{generate, add_predict_job, prediction_paths, predict_events};
 
 mod types;
 
@@ -38,9 +38,18 @@ async fn main()-> std::io::Result<()>{
             .service(generate_tokens)
             .service(generate_test_data)
             .service(burn_generate)
+            .service(export_model)
+            .service(prune_model)
+            .service(analyze_model)
+            .service(model_analysis)
             .service(init)
+            .service(composite)
+            .service(refine)
+            .service(route)
             .service(generate)
             .service(add_predict_job)
+            .service(prediction_paths)
+            .service(predict_events)
             .route("/", web::get().to(health))
     })
     .bind(("0.0.0.0", port))?
@@ -1,5 +1,5 @@
 use log::info;
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use sentry::Data;
 use serde::{Serialize, Deserialize};
 
@@ -29,7 +29,7 @@ impl Clone for Embedding {
 // get all the collections from the token database
 // for each collection, get the data
 // find the unique tokens in the data
-pub async fn generate_unique_tokens() {
+pub async fn generate_unique_tokens(rng_seed: u64) {
 
     // get collections in the token database
     let mongo_client = MongoClient::new().await;
@@ -63,7 +63,9 @@ pub async fn generate_unique_tokens() {
     info!("Unique tokens size: {:?}", unique_tokens.len());
     
     // initialize embeddings
-    let embedings = initialize_embedings(unique_tokens, 100).await;
+    info!("Initializing embeddings with seed {}", rng_seed);
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    let embedings = initialize_embedings(unique_tokens, 100, &mut rng).await;
 
     // save embeddings to database
     mongo_client.insert_embeddings(embedings).await;
@@ -71,10 +73,9 @@ pub async fn generate_unique_tokens() {
 
 }
 
-pub async fn initialize_embedings(tokens: Vec<f64>, parameters_size: usize)->Vec<Embedding>{
+pub async fn initialize_embedings<R: Rng + ?Sized>(tokens: Vec<f64>, parameters_size: usize, rng: &mut R)->Vec<Embedding>{
     let mut embeddings = Vec::new();
     for token in tokens {
-        let mut rng = rand::thread_rng();
         let random_numbers: Vec<f64> = (0..parameters_size)
             .map(|_| rng.gen_range(-1.00..=1.00))
             .collect();
@@ -1,8 +1,10 @@
 use apalis::prelude::{Job, JobContext, JobResult, JobError};
-use aslan_data::DataNode;
+use aslan_aco::AntColony;
+use aslan_tsp::RouteOptimizer;
+use aslan_data::{DataNode, Direction, DistanceScoring, EdgeWeighting, MergeWeighting, ModelSource};
 use serde::{Serialize, Deserialize};
-use log::{info};
-use rand::prelude::*;
+use log::{error, info};
+use rand::{prelude::*, rngs::StdRng};
 use crate::api::task;
 use crate::db::mongodb::MongoClient;
 use crate::api::predict::{generate_prediction};
@@ -14,6 +16,13 @@ pub struct TrainJob {
     pub path: String,
     pub market: String,
     pub status: Status,
+    #[serde(default)]
+    pub scoring: DistanceScoring,
+    #[serde(default)]
+    pub weighting: EdgeWeighting,
+    // seed of the training rng, a random one is drawn when not set
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -59,7 +68,7 @@ impl Job for TrainJob {
     const NAME: &'static str = "apalis::TrainJob";
 }
 
-pub async fn build_model_v2(symbol: String, path: String, market: String){
+pub async fn build_model_v2(symbol: String, path: String, market: String, scoring: DistanceScoring, weighting: EdgeWeighting, rng_seed: u64){
     // get symbols from database
     let mongo_client = MongoClient::new().await;
     let symbols = mongo_client.get_symbols(market.clone()).await;
@@ -100,10 +109,11 @@ pub async fn build_model_v2(symbol: String, path: String, market: String){
     }
 
     info!("Initializing data");
-    let (_, mut nodes) = initialize_data_v2(&full_normalized_data);
+    let (_, mut nodes) = initialize_data_v2(&full_normalized_data, &scoring, &weighting);
 
-    info!("Training model with wavereduce");
-    wavereduce_training(full_data, nodes.as_mut(), 100, 7);
+    info!("Training model with wavereduce using seed {}", rng_seed);
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    wavereduce_training(full_data, nodes.as_mut(), 100, 7, &weighting, &mut rng);
 
     mongo_client.export_data("OMEGA".to_string(), nodes,path.clone(),market.clone()).await;
     info!("Building data model complete");
@@ -113,8 +123,126 @@ pub async fn build_model_v2(symbol: String, path: String, market: String){
     mongo_client.add_model_entry("OMEGA".to_string(), path.clone(),market.clone()).await;
 
 }
+// refines the edge weights of a stored model with ant colony optimization and saves it under a new name
+pub async fn refine_model(symbol: String, name: String, path: String, market: String, colony: AntColony, weighting: EdgeWeighting, rng_seed: u64){
+    let mongo_client = MongoClient::new().await;
+    if !mongo_client.find_model_entry(symbol.clone(), path.clone()).await {
+        info!("No model for {}. Skipping", symbol);
+        return;
+    }
+    let nodes = mongo_client.load_model(symbol.clone(), path.clone(), market.clone()).await;
+    let data = mongo_client.get_symbol_data(symbol.clone(), path.clone(), market.clone()).await;
+    let normalized_data = aslan_data::AslanDataChunks::normalize_data(&data);
+
+    info!("Refining model with ant colony optimization using seed {}", rng_seed);
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    let result = colony.run(&nodes, &normalized_data, 7, &weighting, &mut rng);
+    info!("Colony finished after {} iterations with best error {}", result.trace.len(), result.best_error);
+
+    mongo_client.save_colony_trace(name.clone(), path.clone(), &result).await;
+    mongo_client.replace_model(name.clone(), result.nodes, path.clone(), market.clone()).await;
+    mongo_client.add_model_entry(name, path, market).await;
+    info!("Refining model complete");
+}
+
+// strengthens the connections a cheap traveling salesman route over a stored model uses and saves it under a new name
+pub async fn route_model(symbol: String, name: String, path: String, market: String, optimizer: RouteOptimizer, weighting: EdgeWeighting){
+    let mongo_client = MongoClient::new().await;
+    if !mongo_client.find_model_entry(symbol.clone(), path.clone()).await {
+        info!("No model for {}. Skipping", symbol);
+        return;
+    }
+    let nodes = mongo_client.load_model(symbol.clone(), path.clone(), market.clone()).await;
+    let data = mongo_client.get_symbol_data(symbol.clone(), path.clone(), market.clone()).await;
+    let normalized_data = aslan_data::AslanDataChunks::normalize_data(&data);
+
+    info!("Optimizing node route");
+    let result = optimizer.run(&nodes, &normalized_data, &weighting);
+    info!("Route cost reduced from {} to {} in {} passes", result.initial_cost, result.cost, result.passes);
+
+    mongo_client.replace_model(name.clone(), result.nodes, path.clone(), market.clone()).await;
+    mongo_client.add_model_entry(name, path, market).await;
+    info!("Routing model complete");
+}
+
+// composes already built per-symbol models into a single model, e.g. for a sector or the whole market
+pub async fn build_composite_model(name: String, symbols: Vec<String>, path: String, market: String, merge_weighting: MergeWeighting, weighting: EdgeWeighting, rng_seed: u64){
+    let mongo_client = MongoClient::new().await;
+    let mut symbols = if symbols.is_empty() {
+        mongo_client.get_symbols(market.clone()).await
+    } else {
+        symbols
+    };
+    // every symbol gets its own stream drawn in a fixed order so the tasks can run in any order
+    symbols.sort();
+    let mut seed_rng = StdRng::seed_from_u64(rng_seed);
+
+    let mut tasks = Vec::new();
+    for symbol in symbols{
+        let mut rng = StdRng::seed_from_u64(seed_rng.gen());
+        let path = path.clone();
+        let market = market.clone();
+        let mongo_client = mongo_client.clone();
+        let task = tokio::spawn(async move {
+            if !mongo_client.find_model_entry(symbol.clone(), path.clone()).await {
+                info!("No model for {}. Skipping", symbol);
+                return None;
+            }
+            let nodes = mongo_client.load_model(symbol.clone(), path.clone(), market.clone()).await;
+            let data = mongo_client.get_symbol_data(symbol.clone(), path.clone(), market.clone()).await;
+
+            let data_length = data.len();
+
+            // backtest on the most recent data only, it is only used to rank the models
+            let backtest = tokio::task::spawn_blocking(move || {
+                let accuracy = match merge_weighting {
+                    MergeWeighting::Accuracy if data.len() > 1 => {
+                        let test_data = data[data.len().saturating_sub(70)..].to_vec();
+                        let loss_breakdown = evaluate_loss_function(&test_data, &nodes, 7, "composite".to_string(), &mut rng);
+                        1.0 / (1.0 + relative_loss(&test_data, loss_breakdown.average_loss))
+                    },
+                    _ => 0.0,
+                };
+                (nodes, accuracy)
+            }).await;
+            let (nodes, accuracy) = match backtest {
+                Ok(backtest) => backtest,
+                Err(e) => {
+                    error!("Backtest of {} failed: {}", symbol, e);
+                    return None;
+                }
+            };
+
+            Some(ModelSource{
+                nodes,
+                data_length,
+                accuracy,
+            })
+        });
+        tasks.push(task);
+    }
+
+    let mut sources = Vec::new();
+    for task in tasks{
+        if let Some(source) = task.await.unwrap() {
+            sources.push(source);
+        }
+    }
+    if sources.is_empty() {
+        info!("No models found to compose {}", name);
+        return;
+    }
+
+    info!("Merging {} models into {}", sources.len(), name);
+    let nodes = DataNode::merge_models(sources, merge_weighting, &weighting);
+
+    mongo_client.replace_model(name.clone(), nodes, path.clone(), market.clone()).await;
+    mongo_client.add_model_entry(name, path, market).await;
+    info!("Building composite model complete");
+}
+
 // TODO: propergate errors up stack
-pub async fn build_model(symbol: String, path: String, market: String) {
+pub async fn build_model(symbol: String, path: String, market: String, scoring: DistanceScoring, weighting: EdgeWeighting, rng_seed: u64) {
     info!("Building data model for {}", symbol);
     let mongo_client = MongoClient::new().await;
 
@@ -146,10 +274,11 @@ pub async fn build_model(symbol: String, path: String, market: String) {
 
     //initialize the data
     info!("Initializing data");
-    let (_, mut nodes) = initialize_data_v2(&data);
+    let (_, mut nodes) = initialize_data_v2(&data, &scoring, &weighting);
 
-    info!("Training model with wavereduce");
-    wavereduce_training(data, nodes.as_mut(), 100, 7);
+    info!("Training model with wavereduce using seed {}", rng_seed);
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    wavereduce_training(data, nodes.as_mut(), 100, 7, &weighting, &mut rng);
 
     mongo_client.export_data(symbol.clone(), nodes,"OMEGA".to_string(),market.clone()).await;
     info!("Building data model complete");
@@ -158,37 +287,47 @@ pub async fn build_model(symbol: String, path: String, market: String) {
 
 
 
-fn initialize_data(data: &Vec<f64>) -> (Vec<f64>, Vec<DataNode>) {
+fn initialize_data(data: &Vec<f64>, scoring: &DistanceScoring, weighting: &EdgeWeighting) -> (Vec<f64>, Vec<DataNode>) {
     let normalized_data = aslan_data::AslanDataChunks::normalize_data(data);
     let mut nodes_v2 = aslan_data::DataNode::generate_nodes(&normalized_data, 0.07);
     aslan_data::DataNode::initialize_node_edges(nodes_v2.as_mut());
     let averaged_data = aslan_data::DataNode::parse_data(&nodes_v2, &normalized_data);
-    aslan_data::DataNode::set_distance_scores(nodes_v2.as_mut(), &averaged_data);
-    aslan_data::DataNode::set_weights(nodes_v2.as_mut());
+    aslan_data::DataNode::set_distance_scores(nodes_v2.as_mut(), &averaged_data, scoring);
+    let node_set = build_node_set(&normalized_data);
+    aslan_data::DataNode::set_transition_scores(nodes_v2.as_mut(), &node_set);
+    aslan_data::DataNode::set_weights(nodes_v2.as_mut(), weighting);
     (averaged_data, nodes_v2)
 }
 
 #[time]
-fn initialize_data_v2(normalized_data: &Vec<f64>) -> (Vec<f64>, Vec<DataNode>) {
+fn initialize_data_v2(normalized_data: &Vec<f64>, scoring: &DistanceScoring, weighting: &EdgeWeighting) -> (Vec<f64>, Vec<DataNode>) {
     info!("Generating nodes");
     let mut nodes_v2 = aslan_data::DataNode::generate_nodes(&normalized_data, 0.07);
     info!("Setting distance scores");
-    aslan_data::DataNode::set_distance_scores(nodes_v2.as_mut(), normalized_data);
+    aslan_data::DataNode::set_distance_scores(nodes_v2.as_mut(), normalized_data, scoring);
+    info!("Setting transition scores");
+    let node_set = build_node_set(normalized_data);
+    aslan_data::DataNode::set_transition_scores(nodes_v2.as_mut(), &node_set);
     info!("Setting weights");
-    aslan_data::DataNode::set_weights(nodes_v2.as_mut());
+    aslan_data::DataNode::set_weights(nodes_v2.as_mut(), weighting);
     info!("Initialization complete");
     (normalized_data.to_vec(), nodes_v2)
 }
 
+// collects the values seen before and after every entry of the data
+fn build_node_set(data: &Vec<f64>) -> aslan_data::NodeSet {
+    let mut node_set = aslan_data::NodeSet::new();
+    node_set.parse_data_chunks(aslan_data::AslanDataChunks::new(data.to_vec()));
+    node_set
+}
 
-fn wavereduce_training (data: Vec<f64>,nodes: &mut Vec<DataNode>, iterations: usize, chunk_size: usize){
+fn wavereduce_training<R: Rng + ?Sized> (data: Vec<f64>,nodes: &mut Vec<DataNode>, iterations: usize, chunk_size: usize, weighting: &EdgeWeighting, rng: &mut R){
     // create chunks of the data which will be used to refine the model
     let chunks: Vec<&[f64]> = data.chunks(chunk_size).collect();
 
     // number of times to run the refinement
     for iter in 0..iterations{
         // randomly select a chunk to use for the refinement
-        let mut rng = rand::thread_rng();
         let random_chunk = rng.gen_range(0..chunks.len());
         let chunk = chunks[random_chunk];
 
@@ -198,13 +337,17 @@ fn wavereduce_training (data: Vec<f64>,nodes: &mut Vec<DataNode>, iterations: us
         // generate the results of the refinement
         let waveresultsize = 100;
         let wavereduce = aslan_wavereduce::WaveReduce::new(partition_seed, chunk_size,waveresultsize);
-        let wavereduce_results = wavereduce.generate_results(nodes);
+        let wavereduce_results = wavereduce.generate_results(nodes, rng);
 
         let mut distribution:Vec<WaveDistriubtion>  = Vec::new();
 
         // calculate the standard deviation between the generated data and the actual data
         for result in wavereduce_results.results{
             let generated:Vec<f64> = result.result.into_iter().map(|x| x.state ).collect();
+            // walks stop early at a node without edges and cannot be compared with the whole chunk
+            if generated.len() < chunk_size {
+                continue;
+            }
             let denomalized = denormalize_data(partition_seed, &generated);
             let mut sum = 0.0;
             for (i, value) in chunk.iter().enumerate(){
@@ -230,21 +373,28 @@ fn wavereduce_training (data: Vec<f64>,nodes: &mut Vec<DataNode>, iterations: us
             let node_pointer = dist.generated_data[0];
             let found_node = nodes.iter_mut().find(|x| x.average == node_pointer).unwrap();
             
+            // walks follow the forward table, so that is the one refined
             for data in 1..dist.generated_data.len(){
-                found_node.update_edge(dist.generated_data[data], i as f64);
+                found_node.update_edge(Direction::Forward, dist.generated_data[data], i as f64);
             }
         }
-        aslan_data::DataNode::set_weights(nodes);
+        aslan_data::DataNode::set_weights(nodes, weighting);
     }
     
 }
 
-fn evaluate_loss_function(test_data: &Vec<f64>, nodes: &Vec<DataNode>, chunk_size: usize,stage:String) -> LossBreakdown {
+fn evaluate_loss_function<R: Rng + ?Sized>(test_data: &Vec<f64>, nodes: &Vec<DataNode>, chunk_size: usize,stage:String, rng: &mut R) -> LossBreakdown {
     // create chunks of test data
     let chunks = test_data.chunks(chunk_size);
     let mut loss_breakdown = Vec::new();
     for (i,test_entry )in chunks.enumerate(){
-        let prediction = generate_prediction(&nodes, test_entry[0], test_entry.len());
+        let prediction = match generate_prediction(&nodes, test_entry[0], test_entry.len(), rng) {
+            Some(prediction) => prediction,
+            None => {
+                info!("No full length walk from {} At Iteration {}. Skipping", test_entry[0], i);
+                continue;
+            }
+        };
         info!("Test Data: {:?}", test_entry);
         info!("Prediction: {:?}", prediction.generated_data);
 
@@ -280,6 +430,19 @@ fn evaluate_loss_function(test_data: &Vec<f64>, nodes: &Vec<DataNode>, chunk_siz
 
 }
 
+// the loss in units of the average move of the test data, so models of differently priced symbols compare
+// falls back to the price level when the price never moved
+fn relative_loss(test_data: &Vec<f64>, average_loss: f64) -> f64 {
+    let moves = test_data.windows(2).map(|x| (x[1] - x[0]).abs()).sum::<f64>() / (test_data.len().max(2) - 1) as f64;
+    let price_level = (test_data.iter().sum::<f64>() / test_data.len().max(1) as f64).abs();
+    if moves > 0.0 {
+        average_loss / moves
+    } else if price_level > 0.0 {
+        average_loss / price_level
+    } else {
+        average_loss
+    }
+}
 
 fn denormalize_data(seed: f64, data: &Vec<f64>) -> Vec<f64> {
     let mut denormalized_data = Vec::new();
@@ -0,0 +1,162 @@
+use std::sync::OnceLock;
+use rand::prelude::*;
+
+use crate::node_schema::{DataNode, Direction, Edge};
+
+// walker alias table over the weights of an edge list, draws an edge in constant time
+// every slot holds the share of its own edge and the edge that fills the rest of the slot
+#[derive(Debug,Clone)]
+pub struct AliasTable {
+    probability: Vec<f64>,
+    alias: Vec<usize>,
+}
+
+// alias tables of a node, built on the first draw and dropped whenever the weights can change
+// never stored with the model
+#[derive(Debug,Clone,Default)]
+pub(crate) struct EdgeSamplers {
+    forward: OnceLock<AliasTable>,
+    backward: OnceLock<AliasTable>,
+}
+
+impl AliasTable {
+    // negative, infinite and nan weights count as zero, when none are left every edge is equally likely
+    // so the draws follow the same distribution as a scan over the usable weights
+    pub fn new(edges: &Vec<Edge>) -> Self {
+        let length = edges.len();
+        let usable: Vec<f64> = edges.iter().map(|x| if x.weight > 0.0 && x.weight.is_finite() { x.weight } else { 0.0 }).collect();
+        let total: f64 = usable.iter().sum();
+        let weights: Vec<f64> = if total > 0.0 && total.is_finite() {
+            usable.iter().map(|x| x * length as f64 / total).collect()
+        } else {
+            vec![1.0; length]
+        };
+
+        let mut probability = vec![1.0; length];
+        let mut alias: Vec<usize> = (0..length).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..length).partition(|x| weights[*x] < 1.0);
+        let mut scaled = weights;
+
+        // pair every slot below one with a slot above one that fills it up
+        while let (Some(less), Some(more)) = (small.pop(), large.pop()) {
+            probability[less] = scaled[less];
+            alias[less] = more;
+            scaled[more] -= 1.0 - scaled[less];
+            if scaled[more] < 1.0 {
+                small.push(more);
+            } else {
+                large.push(more);
+            }
+        }
+        // whatever is left is full up to rounding
+        for index in small.into_iter().chain(large.into_iter()) {
+            probability[index] = 1.0;
+        }
+
+        AliasTable { probability, alias }
+    }
+
+    // none when there are no edges to draw from
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<usize> {
+        if self.probability.is_empty() {
+            return None;
+        }
+        let slot = rng.gen_range(0..self.probability.len());
+        if rng.gen::<f64>() < self.probability[slot] {
+            Some(slot)
+        } else {
+            Some(self.alias[slot])
+        }
+    }
+}
+
+impl DataNode {
+    // draws the index of one of the edges a walk in the given direction follows, weighted by the edge weights
+    // none when the node has no edges in that direction
+    pub fn sample_edge<R: Rng + ?Sized>(&self, direction: Direction, rng: &mut R) -> Option<usize> {
+        self.sampler(direction).sample(rng)
+    }
+
+    // builds the alias tables of every node up front, for example right after a model is loaded
+    pub fn build_samplers(nodes: &Vec<DataNode>) {
+        for node in nodes {
+            node.sampler(Direction::Forward);
+            node.sampler(Direction::Backward);
+        }
+    }
+
+    // has to be called after the edge weights are changed without set_weights or get_edges_mut
+    pub fn invalidate_samplers(&mut self) {
+        self.samplers = EdgeSamplers::default();
+    }
+
+    fn sampler(&self, direction: Direction) -> &AliasTable {
+        let sampler = match direction {
+            Direction::Forward => &self.samplers.forward,
+            Direction::Backward => &self.samplers.backward,
+        };
+        sampler.get_or_init(|| AliasTable::new(self.get_edges(direction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    fn edges(weights: &[f64]) -> Vec<Edge> {
+        weights.iter().enumerate().map(|(index, weight)| Edge { value: index as f64, score: 0.0, weight: *weight }).collect()
+    }
+
+    fn frequencies(table: &AliasTable, length: usize) -> Vec<f64> {
+        let draws = 200_000;
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = vec![0usize; length];
+        for _ in 0..draws {
+            counts[table.sample(&mut rng).unwrap()] += 1;
+        }
+        counts.iter().map(|x| *x as f64 / draws as f64).collect()
+    }
+
+    fn assert_frequencies(weights: &[f64], expected: &[f64]) {
+        let drawn = frequencies(&AliasTable::new(&edges(weights)), weights.len());
+        for (drawn, expected) in drawn.iter().zip(expected.iter()) {
+            assert!((drawn - expected).abs() < 0.01, "{:?} drawn for {:?}, expected {:?}", drawn, weights, expected);
+        }
+    }
+
+    #[test]
+    fn draws_follow_the_weights() {
+        assert_frequencies(&[0.1, 0.2, 0.3, 0.4], &[0.1, 0.2, 0.3, 0.4]);
+        assert_frequencies(&[5.0, 0.0, 1.0, 2.0], &[0.625, 0.0, 0.125, 0.25]);
+        assert_frequencies(&[1.0], &[1.0]);
+    }
+
+    #[test]
+    fn unusable_weights_count_as_zero() {
+        assert_frequencies(&[1.0, 1.0, -1.0], &[0.5, 0.5, 0.0]);
+        assert_frequencies(&[1.0, f64::NAN, 3.0, f64::INFINITY], &[0.25, 0.0, 0.75, 0.0]);
+        // nothing usable is left, so every edge is equally likely
+        assert_frequencies(&[0.0, -2.0, f64::NAN, 0.0], &[0.25, 0.25, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn empty_tables_draw_nothing() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(AliasTable::new(&Vec::new()).sample(&mut rng), None);
+        assert_eq!(DataNode::new(0.0).sample_edge(Direction::Forward, &mut rng), None);
+    }
+
+    #[test]
+    fn changed_weights_rebuild_the_table() {
+        let mut node = DataNode::new(0.0);
+        node.forward_edges = edges(&[1.0, 0.0]);
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(node.sample_edge(Direction::Forward, &mut rng), Some(0));
+
+        let edges = node.get_edges_mut(Direction::Forward);
+        edges[0].weight = 0.0;
+        edges[1].weight = 1.0;
+        assert_eq!(node.sample_edge(Direction::Forward, &mut rng), Some(1));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::node_schema::{DataNode, Direction};
+
+// treats a trained graph as a markov chain over its nodes to judge what the model has learned
+// the chain is made lazy (stays put half of the time) so periodic graphs still converge,
+// which keeps the stationary distribution but roughly doubles the mixing time
+#[derive(Debug, Clone)]
+pub struct MarkovAnalyzer {
+    pub direction: Direction,
+    // nodes that return to themselves with at least this probability are reported as near-absorbing
+    pub absorbing_threshold: f64,
+    pub tolerance: f64,
+    pub max_iterations: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NodeAnalysis {
+    pub average: f64,
+    pub stationary: f64,
+    // bits of uncertainty about the next node
+    pub entropy: f64,
+    // expected value and variance of the next move
+    pub drift: f64,
+    pub variance: f64,
+    pub self_transition: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MarkovAnalysis {
+    pub nodes: Vec<NodeAnalysis>,
+    // averages of the nodes that keep or nearly keep the walk in place, or have nowhere to go
+    pub absorbing: Vec<f64>,
+    // stationary weighted entropy and drift of the whole chain
+    pub entropy_rate: f64,
+    pub expected_drift: f64,
+    // steps until every tested start is within 0.25 total variation of the stationary distribution
+    pub mixing_time: Option<usize>,
+    // whether the power iteration for the stationary distribution settled within max_iterations,
+    // and the total change of its last step
+    #[serde(default)]
+    pub converged: bool,
+    #[serde(default)]
+    pub residual: f64,
+}
+
+impl MarkovAnalyzer {
+    pub fn new() -> Self {
+        MarkovAnalyzer {
+            direction: Direction::Forward,
+            absorbing_threshold: 0.9,
+            tolerance: 1e-9,
+            max_iterations: 1000,
+        }
+    }
+
+    pub fn analyze(&self, nodes: &Vec<DataNode>) -> MarkovAnalysis {
+        let transitions = self.transitions(nodes);
+        let (stationary, residual) = self.stationary_distribution(&transitions);
+
+        let mut analysis_nodes = Vec::new();
+        let mut absorbing = Vec::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let row = &transitions[index];
+            let entropy = -row.iter().filter(|x| x.1 > 0.0).map(|x| x.1 * x.1.log2()).sum::<f64>();
+            let drift = row.iter().map(|x| x.1 * nodes[x.0].average).sum::<f64>();
+            let variance = row.iter().map(|x| x.1 * (nodes[x.0].average - drift).powi(2)).sum::<f64>();
+            let self_transition = row.iter().filter(|x| x.0 == index).map(|x| x.1).sum::<f64>();
+
+            if row.is_empty() || self_transition >= self.absorbing_threshold {
+                absorbing.push(node.average);
+            }
+            analysis_nodes.push(NodeAnalysis {
+                average: node.average,
+                stationary: stationary[index],
+                entropy,
+                drift,
+                variance,
+                self_transition,
+            });
+        }
+
+        MarkovAnalysis {
+            entropy_rate: analysis_nodes.iter().map(|x| x.stationary * x.entropy).sum(),
+            expected_drift: analysis_nodes.iter().map(|x| x.stationary * x.drift).sum(),
+            mixing_time: self.mixing_time(&transitions, &stationary),
+            nodes: analysis_nodes,
+            absorbing,
+            converged: residual < self.tolerance,
+            residual,
+        }
+    }
+
+    // sparse transition rows of (target node index, probability)
+    fn transitions(&self, nodes: &Vec<DataNode>) -> Vec<Vec<(usize, f64)>> {
+        let node_indexes: HashMap<String, usize> = nodes.iter().enumerate().map(|(i, x)| (x.average.to_string(), i)).collect();
+        nodes.iter().map(|node| {
+            let row: Vec<(usize, f64)> = node.get_edges(self.direction).iter()
+                .filter(|edge| edge.weight > 0.0 && edge.weight.is_finite())
+                .filter_map(|edge| node_indexes.get(&edge.value.to_string()).map(|target| (*target, edge.weight)))
+                .collect();
+            // edges leading outside the graph are dropped so the rest still sums to one
+            let total: f64 = row.iter().map(|x| x.1).sum();
+            row.into_iter().map(|x| (x.0, x.1 / total)).collect()
+        }).collect()
+    }
+
+    // one step of the lazy chain, nodes without edges keep their mass
+    fn step(transitions: &Vec<Vec<(usize, f64)>>, distribution: &Vec<f64>) -> Vec<f64> {
+        let mut next = vec![0.0; distribution.len()];
+        for (index, row) in transitions.iter().enumerate() {
+            let mass = distribution[index];
+            if row.is_empty() {
+                next[index] += mass;
+                continue;
+            }
+            next[index] += 0.5 * mass;
+            for (target, probability) in row.iter() {
+                next[*target] += 0.5 * mass * probability;
+            }
+        }
+        next
+    }
+
+    // returns the distribution and the total change of the last step
+    fn stationary_distribution(&self, transitions: &Vec<Vec<(usize, f64)>>) -> (Vec<f64>, f64) {
+        let size = transitions.len();
+        if size == 0 {
+            return (Vec::new(), 0.0);
+        }
+        let mut distribution = vec![1.0 / size as f64; size];
+        let mut residual = f64::INFINITY;
+        for _ in 0..self.max_iterations {
+            let next = MarkovAnalyzer::step(transitions, &distribution);
+            residual = next.iter().zip(distribution.iter()).map(|(a, b)| (a - b).abs()).sum();
+            distribution = next;
+            if residual < self.tolerance {
+                break;
+            }
+        }
+        (distribution, residual)
+    }
+
+    // starts from a handful of single nodes spread across the graph
+    fn mixing_time(&self, transitions: &Vec<Vec<(usize, f64)>>, stationary: &Vec<f64>) -> Option<usize> {
+        let size = transitions.len();
+        if size == 0 {
+            return None;
+        }
+        let starts = size.min(10);
+        let mut mixing_time = 0;
+        for start in 0..starts {
+            let mut distribution = vec![0.0; size];
+            distribution[start * size / starts] = 1.0;
+
+            let mut steps = 0;
+            loop {
+                let distance = 0.5 * distribution.iter().zip(stationary.iter()).map(|(a, b)| (a - b).abs()).sum::<f64>();
+                if distance <= 0.25 {
+                    break;
+                }
+                if steps >= self.max_iterations {
+                    return None;
+                }
+                distribution = MarkovAnalyzer::step(transitions, &distribution);
+                steps += 1;
+            }
+            mixing_time = mixing_time.max(steps);
+        }
+        Some(mixing_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_schema::Edge;
+
+    fn node(average: f64, edges: &[(f64, f64)]) -> DataNode {
+        let mut node = DataNode::new(average);
+        for (value, weight) in edges {
+            let mut edge = Edge::new(*value);
+            edge.score = *weight;
+            edge.weight = *weight;
+            node.forward_edges.push(edge);
+        }
+        node
+    }
+
+    #[test]
+    fn two_node_chain() {
+        // 0 always moves to 1, 1 stays or returns with equal odds, so pi = (1/3, 2/3)
+        let nodes = vec![node(0.0, &[(1.0, 1.0)]), node(1.0, &[(0.0, 0.5), (1.0, 0.5)])];
+        let analysis = MarkovAnalyzer::new().analyze(&nodes);
+
+        assert!(analysis.converged);
+        assert!(analysis.residual < 1e-9);
+        assert!((analysis.nodes[0].stationary - 1.0 / 3.0).abs() < 1e-6);
+        assert!((analysis.nodes[1].stationary - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(analysis.nodes[0].entropy, 0.0);
+        assert!((analysis.nodes[1].entropy - 1.0).abs() < 1e-12);
+        assert!((analysis.entropy_rate - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(analysis.nodes[1].self_transition, 0.5);
+        assert!(analysis.absorbing.is_empty());
+        assert!(analysis.mixing_time.is_some());
+    }
+
+    #[test]
+    fn absorbing_state_takes_all_the_mass() {
+        let nodes = vec![node(0.0, &[(1.0, 1.0)]), node(1.0, &[(2.0, 1.0)]), node(2.0, &[])];
+        let analysis = MarkovAnalyzer::new().analyze(&nodes);
+
+        assert!(analysis.converged);
+        assert!(analysis.nodes[0].stationary < 1e-6);
+        assert!(analysis.nodes[1].stationary < 1e-6);
+        assert!((analysis.nodes[2].stationary - 1.0).abs() < 1e-6);
+        assert_eq!(analysis.absorbing, vec![2.0]);
+        assert!(analysis.entropy_rate.abs() < 1e-6);
+    }
+
+    #[test]
+    fn reports_a_stationary_distribution_that_did_not_settle() {
+        let nodes = vec![node(0.0, &[(1.0, 1.0)]), node(1.0, &[(0.0, 0.5), (1.0, 0.5)])];
+        let mut analyzer = MarkovAnalyzer::new();
+        analyzer.max_iterations = 1;
+        let analysis = analyzer.analyze(&nodes);
+
+        assert!(!analysis.converged);
+        assert!(analysis.residual > analyzer.tolerance);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::node_schema::{DataNode, Direction, Edge};
+
+// writes a trained graph out as GraphViz DOT or GraphML so it can be inspected in tools like Gephi
+#[derive(Debug, Clone)]
+pub struct GraphExport {
+    // keep only the heaviest edges of every node
+    pub top_edges: Option<usize>,
+    // drop edges with a weight below this
+    pub min_weight: f64,
+    // which edge table to export, the undirected edges as an undirected graph when none is given
+    pub direction: Option<Direction>,
+}
+
+// an edge that survived the filters, pointing at the index of its target node
+struct ExportEdge<'a> {
+    source: usize,
+    target: usize,
+    edge: &'a Edge,
+}
+
+impl GraphExport {
+    pub fn new() -> Self {
+        GraphExport {
+            top_edges: None,
+            min_weight: 0.0,
+            direction: None,
+        }
+    }
+
+    pub fn to_dot(&self, nodes: &Vec<DataNode>) -> String {
+        let mut output = String::new();
+        // the undirected edges are written as a plain graph, each pair once
+        let (graph, connector) = if self.direction.is_some() { ("digraph", "->") } else { ("graph", "--") };
+        writeln!(output, "{} aslan {{", graph).unwrap();
+        for (index, node) in nodes.iter().enumerate() {
+            writeln!(
+                output,
+                "    n{} [label=\"{}\", average={}, members={}];",
+                index, node.average, node.average, node.members.len()
+            ).unwrap();
+        }
+        for export_edge in self.select_edges(nodes) {
+            writeln!(
+                output,
+                "    n{} {} n{} [label=\"{:.4}\", score={}, weight={}];",
+                export_edge.source, connector, export_edge.target, export_edge.edge.weight, export_edge.edge.score, export_edge.edge.weight
+            ).unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+        output
+    }
+
+    pub fn to_graphml(&self, nodes: &Vec<DataNode>) -> String {
+        let mut output = String::new();
+        writeln!(output, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(output, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">").unwrap();
+        writeln!(output, "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>").unwrap();
+        writeln!(output, "  <key id=\"average\" for=\"node\" attr.name=\"average\" attr.type=\"double\"/>").unwrap();
+        writeln!(output, "  <key id=\"members\" for=\"node\" attr.name=\"members\" attr.type=\"int\"/>").unwrap();
+        writeln!(output, "  <key id=\"score\" for=\"edge\" attr.name=\"score\" attr.type=\"double\"/>").unwrap();
+        writeln!(output, "  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>").unwrap();
+        let edgedefault = if self.direction.is_some() { "directed" } else { "undirected" };
+        writeln!(output, "  <graph id=\"aslan\" edgedefault=\"{}\">", edgedefault).unwrap();
+        for (index, node) in nodes.iter().enumerate() {
+            writeln!(output, "    <node id=\"n{}\">", index).unwrap();
+            writeln!(output, "      <data key=\"label\">{}</data>", node.average).unwrap();
+            writeln!(output, "      <data key=\"average\">{}</data>", node.average).unwrap();
+            writeln!(output, "      <data key=\"members\">{}</data>", node.members.len()).unwrap();
+            writeln!(output, "    </node>").unwrap();
+        }
+        for (index, export_edge) in self.select_edges(nodes).iter().enumerate() {
+            writeln!(
+                output,
+                "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+                index, export_edge.source, export_edge.target
+            ).unwrap();
+            writeln!(output, "      <data key=\"score\">{}</data>", export_edge.edge.score).unwrap();
+            writeln!(output, "      <data key=\"weight\">{}</data>", export_edge.edge.weight).unwrap();
+            writeln!(output, "    </edge>").unwrap();
+        }
+        writeln!(output, "  </graph>").unwrap();
+        writeln!(output, "</graphml>").unwrap();
+        output
+    }
+
+    fn select_edges<'a>(&self, nodes: &'a Vec<DataNode>) -> Vec<ExportEdge<'a>> {
+        let node_indexes: HashMap<String, usize> = nodes.iter().enumerate().map(|(i, node)| (node.average.to_string(), i)).collect();
+
+        let mut selected = Vec::new();
+        let mut pairs = HashSet::new();
+        for (source, node) in nodes.iter().enumerate() {
+            let edges = match self.direction {
+                None => &node.edges,
+                Some(Direction::Forward) => &node.forward_edges,
+                Some(Direction::Backward) => &node.backward_edges,
+            };
+            let mut edges: Vec<&Edge> = edges.iter().filter(|x| x.weight >= self.min_weight).collect();
+            if let Some(top_edges) = self.top_edges {
+                edges.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap_or(std::cmp::Ordering::Equal));
+                edges.truncate(top_edges);
+            }
+
+            for edge in edges {
+                // edges pointing at values without a node have nowhere to go in the graph
+                if let Some(target) = node_indexes.get(&edge.value.to_string()) {
+                    if self.direction.is_none() && !pairs.insert((source.min(*target), source.max(*target))) {
+                        continue;
+                    }
+                    selected.push(ExportEdge { source, target: *target, edge });
+                }
+            }
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_nodes() -> Vec<DataNode> {
+        let mut first = DataNode::new(1.0);
+        let mut second = DataNode::new(2.0);
+        let mut edge = Edge::new(2.0);
+        edge.score = 3.0;
+        edge.weight = 0.75;
+        first.forward_edges.push(edge.clone());
+        first.edges.push(edge);
+        let mut edge = Edge::new(1.0);
+        edge.score = 3.0;
+        edge.weight = 0.75;
+        second.edges.push(edge);
+        vec![first, second]
+    }
+
+    #[test]
+    fn dot_of_two_nodes() {
+        let nodes = two_nodes();
+        let mut graph_export = GraphExport::new();
+        graph_export.direction = Some(Direction::Forward);
+        assert_eq!(
+            graph_export.to_dot(&nodes),
+            "digraph aslan {\n    n0 [label=\"1\", average=1, members=1];\n    n1 [label=\"2\", average=2, members=1];\n    n0 -> n1 [label=\"0.7500\", score=3, weight=0.75];\n}\n"
+        );
+
+        // the undirected table holds the edge from both ends but it is written once
+        graph_export.direction = None;
+        assert_eq!(
+            graph_export.to_dot(&nodes),
+            "graph aslan {\n    n0 [label=\"1\", average=1, members=1];\n    n1 [label=\"2\", average=2, members=1];\n    n0 -- n1 [label=\"0.7500\", score=3, weight=0.75];\n}\n"
+        );
+    }
+
+    #[test]
+    fn graphml_of_two_nodes() {
+        let nodes = two_nodes();
+        let mut graph_export = GraphExport::new();
+        graph_export.direction = Some(Direction::Forward);
+        let graphml = graph_export.to_graphml(&nodes);
+        assert!(graphml.contains("<graph id=\"aslan\" edgedefault=\"directed\">"));
+        assert!(graphml.contains("    <node id=\"n0\">\n      <data key=\"label\">1</data>\n      <data key=\"average\">1</data>\n      <data key=\"members\">1</data>\n    </node>"));
+        assert!(graphml.contains("    <edge id=\"e0\" source=\"n0\" target=\"n1\">\n      <data key=\"score\">3</data>\n      <data key=\"weight\">0.75</data>\n    </edge>"));
+        assert_eq!(graphml.matches("<edge ").count(), 1);
+        assert!(graphml.ends_with("  </graph>\n</graphml>\n"));
+
+        graph_export.direction = None;
+        let graphml = graph_export.to_graphml(&nodes);
+        assert!(graphml.contains("<graph id=\"aslan\" edgedefault=\"undirected\">"));
+        assert_eq!(graphml.matches("<edge ").count(), 1);
+    }
+}
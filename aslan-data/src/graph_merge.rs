@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::node_schema::{DataNode, Edge, EdgeWeighting};
+
+// how much each model contributes to a composite model
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum MergeWeighting {
+    Equal,
+    // by the number of entries the model was trained on
+    DataLength,
+    // by the backtest accuracy of the model
+    Accuracy,
+}
+
+impl Default for MergeWeighting {
+    fn default() -> Self {
+        MergeWeighting::Equal
+    }
+}
+
+// an already built model together with what it is weighted by when merged
+#[derive(Debug,Clone)]
+pub struct ModelSource {
+    pub nodes: Vec<DataNode>,
+    pub data_length: usize,
+    pub accuracy: f64,
+}
+
+impl DataNode {
+    // unions the nodes of every model by average and combines their edge scores
+    // each model's scores are scaled so it contributes its share of the total score mass
+    pub fn merge_models(sources: Vec<ModelSource>, merge_weighting: MergeWeighting, weighting: &EdgeWeighting) -> Vec<DataNode> {
+        let shares = DataNode::model_shares(&sources, merge_weighting);
+        let totals:Vec<f64> = sources.iter().map(|x| DataNode::total_score(&x.nodes)).collect();
+        let combined_total:f64 = totals.iter().sum();
+
+        let mut merged:Vec<DataNode> = Vec::new();
+        let mut node_indexes:HashMap<String, usize> = HashMap::new();
+
+        for (source_index, source) in sources.into_iter().enumerate() {
+            let scale = if totals[source_index] > 0.0 {
+                shares[source_index] * combined_total / totals[source_index]
+            } else {
+                0.0
+            };
+
+            for node in source.nodes {
+                let key = node.average.to_string();
+                let merged_index = match node_indexes.get(&key) {
+                    Some(index) => *index,
+                    None => {
+                        let mut merged_node = DataNode::new(node.average);
+                        merged_node.members = Vec::new();
+                        merged.push(merged_node);
+                        node_indexes.insert(key, merged.len() - 1);
+                        merged.len() - 1
+                    }
+                };
+                let merged_node = &mut merged[merged_index];
+
+                for member in node.members {
+                    if !merged_node.members.contains(&member) {
+                        merged_node.members.push(member);
+                    }
+                }
+                DataNode::merge_edges(&mut merged_node.edges, &node.edges, scale);
+                DataNode::merge_edges(&mut merged_node.forward_edges, &node.forward_edges, scale);
+                DataNode::merge_edges(&mut merged_node.backward_edges, &node.backward_edges, scale);
+            }
+        }
+
+        DataNode::set_weights(&mut merged, weighting);
+        merged
+    }
+
+    // normalized contribution of every model, equal when the chosen measure gives nothing to go on
+    fn model_shares(sources: &Vec<ModelSource>, merge_weighting: MergeWeighting) -> Vec<f64> {
+        let raw:Vec<f64> = sources.iter().map(|source| match merge_weighting {
+            MergeWeighting::Equal => 1.0,
+            MergeWeighting::DataLength => source.data_length as f64,
+            MergeWeighting::Accuracy => source.accuracy.max(0.0),
+        }).collect();
+        let total:f64 = raw.iter().sum();
+
+        if total > 0.0 && total.is_finite() {
+            raw.iter().map(|x| x / total).collect()
+        } else {
+            vec![1.0 / sources.len() as f64; sources.len()]
+        }
+    }
+
+    fn total_score(nodes: &Vec<DataNode>) -> f64 {
+        nodes.iter().map(|node| {
+            node.edges.iter().chain(node.forward_edges.iter()).chain(node.backward_edges.iter()).map(|x| x.score).sum::<f64>()
+        }).sum()
+    }
+
+    pub(crate) fn merge_edges(merged: &mut Vec<Edge>, edges: &Vec<Edge>, scale: f64) {
+        let mut edge_indexes:HashMap<String, usize> = merged.iter().enumerate().map(|(i, x)| (x.value.to_string(), i)).collect();
+        for edge in edges {
+            let key = edge.value.to_string();
+            match edge_indexes.get(&key) {
+                Some(index) => merged[*index].score += edge.score * scale,
+                None => {
+                    let mut merged_edge = Edge::new(edge.value);
+                    merged_edge.score = edge.score * scale;
+                    merged.push(merged_edge);
+                    edge_indexes.insert(key, merged.len() - 1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a model of one node with a single forward edge carrying all of its score
+    fn source(average: f64, target: f64, score: f64, data_length: usize, accuracy: f64) -> ModelSource {
+        let mut node = DataNode::new(average);
+        let mut edge = Edge::new(target);
+        edge.score = score;
+        node.forward_edges.push(edge);
+        ModelSource { nodes: vec![node], data_length, accuracy }
+    }
+
+    fn merged_scores(merge_weighting: MergeWeighting, sources: Vec<ModelSource>) -> Vec<f64> {
+        let merged = DataNode::merge_models(sources, merge_weighting, &EdgeWeighting::default());
+        merged.iter().map(|node| node.forward_edges[0].score).collect()
+    }
+
+    fn assert_scores(drawn: Vec<f64>, expected: &[f64]) {
+        assert_eq!(drawn.len(), expected.len());
+        for (drawn, expected) in drawn.iter().zip(expected.iter()) {
+            assert!((drawn - expected).abs() < 1e-9, "{:?} expected {:?}", drawn, expected);
+        }
+    }
+
+    #[test]
+    fn every_model_contributes_its_share_of_the_score_mass() {
+        // the combined mass of 40 is split by share whatever the models were trained to
+        let sources = || vec![source(1.0, 2.0, 10.0, 300, 0.2), source(3.0, 4.0, 30.0, 100, 0.6)];
+        assert_scores(merged_scores(MergeWeighting::Equal, sources()), &[20.0, 20.0]);
+        assert_scores(merged_scores(MergeWeighting::DataLength, sources()), &[30.0, 10.0]);
+        assert_scores(merged_scores(MergeWeighting::Accuracy, sources()), &[10.0, 30.0]);
+    }
+
+    #[test]
+    fn shares_fall_back_to_equal() {
+        let sources = vec![source(1.0, 2.0, 10.0, 0, -1.0), source(3.0, 4.0, 30.0, 0, 0.0)];
+        assert_scores(merged_scores(MergeWeighting::Accuracy, sources), &[20.0, 20.0]);
+    }
+
+    #[test]
+    fn shared_nodes_add_their_scaled_scores() {
+        let sources = vec![source(1.0, 2.0, 10.0, 1, 0.0), source(1.0, 2.0, 30.0, 1, 0.0)];
+        let merged = DataNode::merge_models(sources, MergeWeighting::Equal, &EdgeWeighting::default());
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].forward_edges.len(), 1);
+        assert_scores(vec![merged[0].forward_edges[0].score], &[40.0]);
+        assert_eq!(merged[0].members, vec![1.0]);
+    }
+}
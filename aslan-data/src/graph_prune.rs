@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::node_schema::{DataNode, Edge, EdgeWeighting};
+
+// thresholds for compacting a trained graph, zero or none disables a step
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub struct PruneOptions {
+    // drop edges whose weight is below this
+    #[serde(default)]
+    pub min_weight: f64,
+    // drop edges whose score is below this
+    #[serde(default)]
+    pub min_score: f64,
+    // keep only the highest scoring edges of every node
+    #[serde(default)]
+    pub top_edges: Option<usize>,
+    // fold nodes whose edges score less than this in total into the closest remaining node
+    #[serde(default)]
+    pub min_node_score: f64,
+}
+
+// size of the graph before and after pruning
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct PruneReport {
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+    pub edges_before: usize,
+    pub edges_after: usize,
+    pub folded_nodes: usize,
+    // average share of a node's score carried by the dropped edges
+    pub lost_mass: f64,
+}
+
+impl DataNode {
+    pub fn prune(nodes: &mut Vec<DataNode>, options: &PruneOptions, weighting: &EdgeWeighting) -> PruneReport {
+        let nodes_before = nodes.len();
+        let edges_before = DataNode::count_edges(nodes);
+
+        let folded_nodes = DataNode::fold_rare_nodes(nodes, options.min_node_score);
+        // folded edges are rebuilt from their scores, weigh them again before min_weight is checked
+        if folded_nodes > 0 {
+            DataNode::set_weights(nodes, weighting);
+        }
+
+        // drop edges and keep track of the share of score that went with them
+        let mut lost_mass = 0.0;
+        let mut tables = 0;
+        for node in nodes.iter_mut() {
+            for edges in [&mut node.edges, &mut node.forward_edges, &mut node.backward_edges] {
+                let total_score:f64 = edges.iter().map(|x| x.score).sum();
+                if edges.is_empty() || total_score <= 0.0 {
+                    continue;
+                }
+                DataNode::prune_edges(edges, options);
+                let kept_score:f64 = edges.iter().map(|x| x.score).sum();
+                lost_mass += 1.0 - kept_score / total_score;
+                tables += 1;
+            }
+        }
+
+        DataNode::set_weights(nodes, weighting);
+
+        PruneReport {
+            nodes_before,
+            nodes_after: nodes.len(),
+            edges_before,
+            edges_after: DataNode::count_edges(nodes),
+            folded_nodes,
+            lost_mass: if tables > 0 { lost_mass / tables as f64 } else { 0.0 },
+        }
+    }
+
+    // a table that had edges keeps at least its highest scoring one, so walks never reach a node they cannot leave
+    fn prune_edges(edges: &mut Vec<Edge>, options: &PruneOptions) {
+        let best = edges.iter().max_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal)).cloned();
+        edges.retain(|x| x.weight >= options.min_weight && x.score >= options.min_score);
+        if let Some(top_edges) = options.top_edges {
+            edges.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+            edges.truncate(top_edges.max(1));
+        }
+        if edges.is_empty() {
+            edges.extend(best);
+        }
+    }
+
+    // merges every rare node into the remaining node with the closest average
+    // and points the edges that led to it at that node instead
+    fn fold_rare_nodes(nodes: &mut Vec<DataNode>, min_node_score: f64) -> usize {
+        let node_score = |node: &DataNode| -> f64 {
+            let edges = if node.forward_edges.is_empty() { &node.edges } else { &node.forward_edges };
+            edges.iter().map(|x| x.score).sum()
+        };
+        let (rare, kept): (Vec<DataNode>, Vec<DataNode>) = nodes.drain(..).partition(|x| node_score(x) < min_node_score);
+
+        // nothing to fold into
+        if rare.is_empty() || kept.is_empty() {
+            nodes.extend(kept);
+            nodes.extend(rare);
+            return 0;
+        }
+        *nodes = kept;
+
+        let mut folded:HashMap<String, f64> = HashMap::new();
+        for rare_node in rare.iter() {
+            let mut neighbour = 0;
+            for i in 0..nodes.len() {
+                if (nodes[i].average - rare_node.average).abs() < (nodes[neighbour].average - rare_node.average).abs() {
+                    neighbour = i;
+                }
+            }
+            folded.insert(rare_node.average.to_string(), nodes[neighbour].average);
+
+            let neighbour_node = &mut nodes[neighbour];
+            for member in rare_node.members.iter() {
+                if !neighbour_node.members.contains(member) {
+                    neighbour_node.members.push(*member);
+                }
+            }
+            DataNode::merge_edges(&mut neighbour_node.edges, &rare_node.edges, 1.0);
+            DataNode::merge_edges(&mut neighbour_node.forward_edges, &rare_node.forward_edges, 1.0);
+            DataNode::merge_edges(&mut neighbour_node.backward_edges, &rare_node.backward_edges, 1.0);
+        }
+
+        for node in nodes.iter_mut() {
+            DataNode::redirect_edges(&mut node.edges, &folded);
+            DataNode::redirect_edges(&mut node.forward_edges, &folded);
+            DataNode::redirect_edges(&mut node.backward_edges, &folded);
+        }
+        rare.len()
+    }
+
+    fn redirect_edges(edges: &mut Vec<Edge>, folded: &HashMap<String, f64>) {
+        let redirected:Vec<Edge> = edges.drain(..).map(|mut edge| {
+            if let Some(average) = folded.get(&edge.value.to_string()) {
+                edge.value = *average;
+            }
+            edge
+        }).collect();
+        DataNode::merge_edges(edges, &redirected, 1.0);
+    }
+
+    fn count_edges(nodes: &Vec<DataNode>) -> usize {
+        nodes.iter().map(|x| x.edges.len() + x.forward_edges.len() + x.backward_edges.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(average: f64, edges: &[(f64, f64)]) -> DataNode {
+        let mut node = DataNode::new(average);
+        node.forward_edges = edges.iter().map(|(value, score)| Edge { value: *value, score: *score, weight: 0.0 }).collect();
+        node
+    }
+
+    #[test]
+    fn folded_edges_are_weighed_before_min_weight() {
+        let mut nodes = vec![
+            node(0.0, &[(0.0, 4.0), (0.1, 4.0), (0.11, 0.2)]),
+            node(0.1, &[(0.0, 3.0), (0.1, 5.0)]),
+            node(0.11, &[(0.0, 0.1), (0.1, 0.1)]),
+        ];
+        DataNode::set_weights(&mut nodes, &EdgeWeighting::default());
+        let options = PruneOptions { min_weight: 0.1, min_score: 0.0, top_edges: None, min_node_score: 1.0 };
+        let report = DataNode::prune(&mut nodes, &options, &EdgeWeighting::default());
+
+        assert_eq!(report.folded_nodes, 1);
+        assert_eq!(report.nodes_after, 2);
+        // the edge into the folded node is merged into the edge to 0.1, nothing else is dropped
+        assert_eq!(report.edges_after, 4);
+        assert!(report.lost_mass.abs() < 1e-12);
+        let first: Vec<(f64, f64)> = nodes[0].forward_edges.iter().map(|x| (x.value, x.score)).collect();
+        assert_eq!(first, vec![(0.0, 4.0), (0.1, 4.2)]);
+        for node in nodes.iter() {
+            let total: f64 = node.forward_edges.iter().map(|x| x.weight).sum();
+            assert!((total - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn pruned_tables_keep_their_best_edge() {
+        let mut nodes = vec![
+            node(0.0, &[(0.0, 1.0), (0.1, 3.0), (0.2, 2.0)]),
+            node(0.1, &[(0.0, 1.0)]),
+        ];
+        DataNode::set_weights(&mut nodes, &EdgeWeighting::default());
+        let options = PruneOptions { min_weight: 0.0, min_score: 10.0, top_edges: None, min_node_score: 0.0 };
+        DataNode::prune(&mut nodes, &options, &EdgeWeighting::default());
+
+        assert_eq!(nodes[0].forward_edges.len(), 1);
+        assert_eq!(nodes[0].forward_edges[0].value, 0.1);
+        assert_eq!(nodes[0].forward_edges[0].weight, 1.0);
+        assert_eq!(nodes[1].forward_edges.len(), 1);
+    }
+}
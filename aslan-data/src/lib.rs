@@ -1,7 +1,17 @@
 mod input_schema;
 mod chunk_schema;
 mod node_schema;
+mod graph_export;
+mod graph_merge;
+mod graph_prune;
+mod graph_analysis;
+mod edge_sampler;
 
 pub use input_schema::{AslanData,DataColumn, DataEntry};
 pub use chunk_schema::{AslanDataChunks,Node,NodeSet};
-pub use node_schema::{DataNode,Edge};
\ No newline at end of file
+pub use node_schema::{DataNode,Edge,Direction,DistanceKernel,DistanceScoring,EdgeWeighting,Smoothing};
+pub use graph_export::GraphExport;
+pub use graph_merge::{MergeWeighting,ModelSource};
+pub use graph_prune::{PruneOptions,PruneReport};
+pub use graph_analysis::{MarkovAnalyzer,MarkovAnalysis,NodeAnalysis};
+pub use edge_sampler::AliasTable;
\ No newline at end of file
@@ -2,11 +2,148 @@ use std::collections::HashMap;
 use polars::prelude::*;
 use serde::{Serialize, Deserialize};
 
+use crate::chunk_schema::NodeSet;
+use crate::edge_sampler::EdgeSamplers;
+
 #[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct DataNode {
     pub average:f64,
     pub members : Vec<f64>,
-    pub edges : Vec<Edge>
+    pub edges : Vec<Edge>,
+    // directed transition tables, empty on models built before they existed
+    #[serde(default)]
+    pub forward_edges : Vec<Edge>,
+    #[serde(default)]
+    pub backward_edges : Vec<Edge>,
+    #[serde(skip)]
+    pub(crate) samplers : EdgeSamplers,
+}
+
+// which way a walk moves through the graph
+// forward follows the values that came after a node, backward the values that came before it
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Direction::Forward
+    }
+}
+
+// how the score an entry adds to an anchor's edge falls off with its distance from the anchor
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum DistanceKernel {
+    // only the directly neighbouring entries score
+    Adjacent,
+    // scores decay^(distance - 1)
+    Exponential { decay: f64 },
+    // scores exp(-(distance - 1)^2 / (2 * sigma^2))
+    Gaussian { sigma: f64 },
+    // scores fall linearly to zero past the end of the window
+    Linear,
+}
+
+// kernel and maximum look-ahead used when scoring the undirected edges
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub struct DistanceScoring {
+    pub kernel: DistanceKernel,
+    pub window: usize,
+}
+
+// how observed scores are smoothed before they are turned into weights
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum Smoothing {
+    // weights are the raw share of the score
+    None,
+    // adds alpha to the score of every edge
+    Additive { alpha: f64 },
+    // discounts every observed score and backs the freed mass off
+    // to how many nodes lead to the edge's value across the graph
+    KneserNey { discount: f64 },
+}
+
+// smoothing plus the temperature the smoothed weights are sharpened or flattened with
+// a temperature at or below zero keeps only the heaviest edge
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub struct EdgeWeighting {
+    pub smoothing: Smoothing,
+    pub temperature: f64,
+}
+
+impl Default for EdgeWeighting {
+    fn default() -> Self {
+        EdgeWeighting {
+            smoothing: Smoothing::None,
+            temperature: 1.0,
+        }
+    }
+}
+
+impl EdgeWeighting {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.smoothing {
+            Smoothing::Additive { alpha } if !(alpha >= 0.0 && alpha.is_finite()) => {
+                return Err(format!("Additive alpha must be at least 0, got {}", alpha));
+            },
+            Smoothing::KneserNey { discount } if !(0.0..=1.0).contains(&discount) => {
+                return Err(format!("Kneser-Ney discount must be between 0 and 1, got {}", discount));
+            },
+            _ => {},
+        }
+        if self.temperature.is_nan() {
+            return Err("Temperature must be a number".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl DistanceScoring {
+    // the adjacent kernel ignores the window, every other kernel needs at least one entry of look-ahead
+    pub fn validate(&self) -> Result<(), String> {
+        match self.kernel {
+            DistanceKernel::Exponential { decay } if !(0.0..=1.0).contains(&decay) => {
+                return Err(format!("Exponential decay must be between 0 and 1, got {}", decay));
+            },
+            DistanceKernel::Gaussian { sigma } if !(sigma > 0.0 && sigma.is_finite()) => {
+                return Err(format!("Gaussian sigma must be above 0, got {}", sigma));
+            },
+            _ => {},
+        }
+        if self.kernel != DistanceKernel::Adjacent && self.window == 0 {
+            return Err("Window must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl DistanceKernel {
+    pub fn score(&self, distance:usize, window:usize) -> f64 {
+        let offset = distance.saturating_sub(1) as f64;
+        match self {
+            DistanceKernel::Adjacent => if distance == 1 { 1.0 } else { 0.0 },
+            DistanceKernel::Exponential { decay } => decay.powf(offset),
+            DistanceKernel::Gaussian { sigma } => (-(offset * offset) / (2.0 * sigma * sigma)).exp(),
+            DistanceKernel::Linear => {
+                if distance > window {
+                    0.0
+                } else {
+                    (window - distance + 1) as f64 / window as f64
+                }
+            },
+        }
+    }
+}
+
+impl Default for DistanceScoring {
+    fn default() -> Self {
+        DistanceScoring {
+            kernel: DistanceKernel::Exponential { decay: 0.5 },
+            window: 7,
+        }
+    }
 }
 
 #[derive(Debug,Serialize, Deserialize,Clone)]
@@ -59,7 +196,10 @@ impl DataNode {
         DataNode{
             average:data,
             members:vec![data],
-            edges:Vec::new()
+            edges:Vec::new(),
+            forward_edges:Vec::new(),
+            backward_edges:Vec::new(),
+            samplers:EdgeSamplers::default(),
         }
     }
 
@@ -76,6 +216,7 @@ impl DataNode {
         for i in 0..nodes.len(){
             let edges:Vec<Edge> = vec![Edge::new(averages[i]); averages.len()];
             nodes[i].edges = edges;
+            nodes[i].invalidate_samplers();
         }
     }
     
@@ -102,54 +243,187 @@ impl DataNode {
         result
     }
 
-    pub fn set_distance_scores (nodes: &mut Vec<DataNode>, averaged_data: &Vec<f64>){
-        // generate an array of all indexes of the occurance of the data in the averaged data
-        // load all edges into a hashmap
-        // for each index split the at the index 
-        // for each [left, right] update the score of the edge decending in value further from the index
+    // scores every edge by how close its value appears to the node in the data
+    // each entry only looks ahead `window` entries so the data is scanned once
+    // and the score is added to both ends of the pair
+    pub fn set_distance_scores (nodes: &mut Vec<DataNode>, averaged_data: &Vec<f64>, scoring: &DistanceScoring){
+        let window = match scoring.kernel {
+            DistanceKernel::Adjacent => 1,
+            _ => scoring.window,
+        };
+
+        // index the nodes by average and their edges by value
+        let node_indexes:HashMap<String, usize> = nodes.iter().enumerate().map(|(i, node)| (node.average.to_string(), i)).collect();
+        let edge_indexes:Vec<HashMap<String, usize>> = nodes.iter()
+            .map(|node| node.edges.iter().enumerate().map(|(i, item)| (item.value.to_string(), i)).collect())
+            .collect();
+        let keys:Vec<String> = averaged_data.iter().map(|x| x.to_string()).collect();
 
-        for node in nodes{
-            // generate an array of all indexes of the occurance of the data in the averaged data
-            let indexes:Vec<usize> = averaged_data.iter().enumerate().filter_map(|(i, x)| if x == &node.average { Some(i) } else { None }).collect();
-
-            // load all edges into a hashmap where the key is the value and the value is its index in the edges array
-            let edges:HashMap<String, usize> = node.edges.iter().enumerate().map(|(i, item)| (item.value.to_string(), i)).collect();
-
-            // for each index split the at the index
-            for index in indexes{
-                let data_copy = averaged_data.clone();
-                let (left,right) = data_copy.split_at(index);
-
-                // for each [left, right] update the score of the edge decending in value further from the index
-                for (index,entry) in left.iter().enumerate(){
-                    // find edge index in array 
-                    let edge_index = edges.get(&entry.to_string()).unwrap().clone();
-                    // update score in the node edges array
-                    let edge = node.edges.get_mut(edge_index).unwrap();
-                    edge.score += index as f64;
+        for anchor in 0..keys.len(){
+            let last = (anchor + window).min(keys.len() - 1);
+            for neighbour in anchor + 1..=last{
+                let score = scoring.kernel.score(neighbour - anchor, window);
+
+                if let Some(node_index) = node_indexes.get(&keys[anchor]){
+                    DataNode::add_edge_score(&mut nodes[*node_index], &edge_indexes[*node_index], &keys[neighbour], score);
+                }
+                if let Some(node_index) = node_indexes.get(&keys[neighbour]){
+                    DataNode::add_edge_score(&mut nodes[*node_index], &edge_indexes[*node_index], &keys[anchor], score);
                 }
+            }
+        }
+    }
+
+    fn add_edge_score(node: &mut DataNode, edge_indexes: &HashMap<String, usize>, key: &String, score: f64){
+        if let Some(edge_index) = edge_indexes.get(key){
+            node.edges[*edge_index].score += score;
+        }
+    }
+
+    // builds the directed transition tables from the before/after connections of a node set
+    // every observed transition adds one to the score of the matching edge
+    pub fn set_transition_scores(nodes: &mut Vec<DataNode>, node_set: &NodeSet){
+        let (_, node_dict) = DataNode::init_map(nodes, &Vec::new());
+
+        for node in nodes.iter_mut(){
+            let mut forward_edges:Vec<Edge> = Vec::new();
+            let mut backward_edges:Vec<Edge> = Vec::new();
 
-                for (index,entry) in right.iter().rev().enumerate(){
-                    // find edge index in array 
-                    let edge_index = edges.get(&entry.to_string()).unwrap().clone();
-                    // update score in the node edges array
-                    let edge = node.edges.get_mut(edge_index).unwrap();
-                    edge.score += index as f64;
+            for member in node.members.iter(){
+                let connections = match node_set.nodes.get(&member.to_string()){
+                    Some(connections) => connections,
+                    None => continue,
+                };
+                for value in connections.connected_nodes_after.iter(){
+                    DataNode::add_transition(&mut forward_edges, &node_dict, *value);
+                }
+                for value in connections.connected_nodes_before.iter(){
+                    DataNode::add_transition(&mut backward_edges, &node_dict, *value);
                 }
             }
+
+            node.forward_edges = forward_edges;
+            node.backward_edges = backward_edges;
+            node.invalidate_samplers();
         }
     }
 
-    pub fn set_weights(nodes: &mut Vec<DataNode>) {
+    fn add_transition(edges: &mut Vec<Edge>, node_dict: &HashMap<String,f64>, value:f64){
+        // map the raw value onto the node that holds it
+        let target = match node_dict.get(&value.to_string()){
+            Some(average) => *average,
+            None => value,
+        };
+        match edges.iter_mut().find(|x| x.value == target){
+            Some(edge) => edge.score += 1.0,
+            None => {
+                let mut edge = Edge::new(target);
+                edge.score = 1.0;
+                edges.push(edge);
+            }
+        }
+    }
+
+    // returns the edges a walk in the given direction should follow
+    // falls back to the undirected edges when the model has no directed tables
+    pub fn get_edges(&self, direction: Direction) -> &Vec<Edge> {
+        let directed_edges = match direction {
+            Direction::Forward => &self.forward_edges,
+            Direction::Backward => &self.backward_edges,
+        };
+        if directed_edges.is_empty() {
+            &self.edges
+        } else {
+            directed_edges
+        }
+    }
+
+    // the caller can change the weights, so the alias tables are rebuilt on the next draw
+    pub fn get_edges_mut(&mut self, direction: Direction) -> &mut Vec<Edge> {
+        self.invalidate_samplers();
+        let directed_empty = match direction {
+            Direction::Forward => self.forward_edges.is_empty(),
+            Direction::Backward => self.backward_edges.is_empty(),
+        };
+        if directed_empty {
+            return &mut self.edges;
+        }
+        match direction {
+            Direction::Forward => &mut self.forward_edges,
+            Direction::Backward => &mut self.backward_edges,
+        }
+    }
+
+    // a node whose edges carry no usable score falls back to the global distribution
+    // and then to uniform weights, so every edge list always sums to one
+    pub fn set_weights(nodes: &mut Vec<DataNode>, weighting: &EdgeWeighting) {
+        let edges_distribution = DataNode::continuation_distribution(nodes.iter().map(|x| &x.edges).collect());
+        let forward_distribution = DataNode::continuation_distribution(nodes.iter().map(|x| &x.forward_edges).collect());
+        let backward_distribution = DataNode::continuation_distribution(nodes.iter().map(|x| &x.backward_edges).collect());
+
         for node in nodes {
-            let total_score: f64 = node.edges.iter().map(|x| x.score).sum();
-            let edges_len = node.edges.len();
-    
-            for i in 0..edges_len {
-                let edge = &mut node.edges[i];
-                let weight = edge.score / total_score;
-                edge.weight = weight;
+            DataNode::set_edge_weights(&mut node.edges, weighting, &edges_distribution);
+            DataNode::set_edge_weights(&mut node.forward_edges, weighting, &forward_distribution);
+            DataNode::set_edge_weights(&mut node.backward_edges, weighting, &backward_distribution);
+            node.invalidate_samplers();
+        }
+    }
+
+    // share of the edge tables that lead to each value with a positive score
+    fn continuation_distribution(tables: Vec<&Vec<Edge>>) -> HashMap<String,f64> {
+        let mut distribution:HashMap<String,f64> = HashMap::new();
+        let mut total = 0.0;
+        for edges in tables {
+            for edge in edges.iter().filter(|x| x.score > 0.0) {
+                *distribution.entry(edge.value.to_string()).or_insert(0.0) += 1.0;
+                total += 1.0;
+            }
+        }
+        for count in distribution.values_mut() {
+            *count /= total;
+        }
+        distribution
+    }
+
+    fn set_edge_weights(edges: &mut Vec<Edge>, weighting: &EdgeWeighting, distribution: &HashMap<String,f64>) {
+        if edges.is_empty() {
+            return;
+        }
+        let total_score: f64 = edges.iter().map(|x| x.score.max(0.0)).sum();
+        let observed = edges.iter().filter(|x| x.score > 0.0).count() as f64;
+
+        let mut weights:Vec<f64> = edges.iter().map(|edge| {
+            let score = edge.score.max(0.0);
+            let background = *distribution.get(&edge.value.to_string()).unwrap_or(&0.0);
+            match weighting.smoothing {
+                Smoothing::None => score,
+                Smoothing::Additive { alpha } => score + alpha,
+                Smoothing::KneserNey { discount } => {
+                    if total_score > 0.0 {
+                        let back_off = discount * observed / total_score;
+                        (score - discount).max(0.0) / total_score + back_off * background
+                    } else {
+                        background
+                    }
+                },
             }
+        }).collect();
+
+        let heaviest = weights.iter().cloned().fold(f64::MIN, f64::max);
+        if weighting.temperature <= 0.0 {
+            weights = weights.iter().map(|x| if *x == heaviest { 1.0 } else { 0.0 }).collect();
+        } else if weighting.temperature != 1.0 && heaviest > 0.0 {
+            // scaled by the heaviest edge so the powers neither overflow nor underflow
+            weights = weights.iter().map(|x| (x / heaviest).powf(1.0 / weighting.temperature)).collect();
+        }
+
+        let total_weight:f64 = weights.iter().sum();
+        for (edge, weight) in edges.iter_mut().zip(weights.iter()) {
+            edge.weight = if total_weight > 0.0 && total_weight.is_finite() {
+                weight / total_weight
+            } else {
+                1.0 / weights.len() as f64
+            };
         }
     }
 
@@ -173,10 +447,152 @@ impl DataNode {
         (data_dict,node_dict)
     }
 
-    pub fn update_edge(self: &mut DataNode, edge:f64, update_score:f64){
-        self.edges.iter_mut().find(|x| x.value == edge).unwrap().score += update_score;
+    // adds to the score of the edge a walk in the given direction follows to the value
+    // an edge the table does not have yet is added, weights change on the next set_weights
+    pub fn update_edge(self: &mut DataNode, direction: Direction, edge:f64, update_score:f64){
+        let edges = self.get_edges_mut(direction);
+        match edges.iter_mut().find(|x| x.value == edge) {
+            Some(found_edge) => found_edge.score += update_score,
+            None => {
+                let mut new_edge = Edge::new(edge);
+                new_edge.score = update_score;
+                edges.push(new_edge);
+            },
+        }
     }
 
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(scores: &[(f64, f64)]) -> Vec<Edge> {
+        scores.iter().map(|(value, score)| Edge { value: *value, score: *score, weight: 0.0 }).collect()
+    }
+
+    fn weights(edges: &Vec<Edge>) -> Vec<f64> {
+        edges.iter().map(|x| x.weight).collect()
+    }
+
+    #[test]
+    fn training_updates_the_forward_weights() {
+        let mut node = DataNode::new(0.0);
+        node.edges = edges(&[(0.0, 1.0), (0.1, 1.0)]);
+        node.forward_edges = edges(&[(0.0, 1.0), (0.1, 1.0)]);
+        let mut nodes = vec![node];
+        DataNode::set_weights(&mut nodes, &EdgeWeighting::default());
+        assert_eq!(weights(nodes[0].get_edges(Direction::Forward)), vec![0.5, 0.5]);
+
+        nodes[0].update_edge(Direction::Forward, 0.1, 2.0);
+        DataNode::set_weights(&mut nodes, &EdgeWeighting::default());
+        assert_eq!(weights(nodes[0].get_edges(Direction::Forward)), vec![0.25, 0.75]);
+        // the undirected table is left alone
+        assert_eq!(weights(&nodes[0].edges), vec![0.5, 0.5]);
+
+        // a value the table has not seen yet gets an edge
+        nodes[0].update_edge(Direction::Forward, 0.2, 4.0);
+        DataNode::set_weights(&mut nodes, &EdgeWeighting::default());
+        assert_eq!(weights(nodes[0].get_edges(Direction::Forward)), vec![0.125, 0.375, 0.5]);
+    }
+
+    #[test]
+    fn distance_kernels() {
+        assert_eq!(DistanceKernel::Adjacent.score(1, 7), 1.0);
+        assert_eq!(DistanceKernel::Adjacent.score(2, 7), 0.0);
+        let exponential = DistanceKernel::Exponential { decay: 0.5 };
+        assert_eq!((exponential.score(1, 7), exponential.score(2, 7), exponential.score(3, 7)), (1.0, 0.5, 0.25));
+        let gaussian = DistanceKernel::Gaussian { sigma: 1.0 };
+        assert_eq!(gaussian.score(1, 7), 1.0);
+        assert!((gaussian.score(3, 7) - (-2.0f64).exp()).abs() < 1e-12);
+        assert_eq!(DistanceKernel::Linear.score(1, 4), 1.0);
+        assert_eq!(DistanceKernel::Linear.score(4, 4), 0.25);
+        assert_eq!(DistanceKernel::Linear.score(5, 4), 0.0);
+    }
+
+    #[test]
+    fn distance_scoring_rejects_unusable_parameters() {
+        let scoring = |kernel, window| DistanceScoring { kernel, window };
+        assert!(DistanceScoring::default().validate().is_ok());
+        assert!(scoring(DistanceKernel::Adjacent, 0).validate().is_ok());
+        assert!(scoring(DistanceKernel::Exponential { decay: 0.0 }, 3).validate().is_ok());
+        assert!(scoring(DistanceKernel::Exponential { decay: 1.0 }, 3).validate().is_ok());
+        assert!(scoring(DistanceKernel::Exponential { decay: -0.5 }, 3).validate().is_err());
+        assert!(scoring(DistanceKernel::Exponential { decay: 1.5 }, 3).validate().is_err());
+        assert!(scoring(DistanceKernel::Exponential { decay: f64::NAN }, 3).validate().is_err());
+        assert!(scoring(DistanceKernel::Gaussian { sigma: 2.0 }, 3).validate().is_ok());
+        assert!(scoring(DistanceKernel::Gaussian { sigma: 0.0 }, 3).validate().is_err());
+        assert!(scoring(DistanceKernel::Gaussian { sigma: -1.0 }, 3).validate().is_err());
+        assert!(scoring(DistanceKernel::Linear, 0).validate().is_err());
+        assert!(scoring(DistanceKernel::Exponential { decay: 0.5 }, 0).validate().is_err());
+    }
+
+    fn weighted(scores: &[(f64, f64)], weighting: EdgeWeighting) -> Vec<f64> {
+        let mut node = DataNode::new(0.0);
+        node.forward_edges = edges(scores);
+        let mut nodes = vec![node];
+        DataNode::set_weights(&mut nodes, &weighting);
+        weights(&nodes[0].forward_edges)
+    }
+
+    fn assert_weights(drawn: Vec<f64>, expected: &[f64]) {
+        assert_eq!(drawn.len(), expected.len());
+        for (drawn, expected) in drawn.iter().zip(expected.iter()) {
+            assert!((drawn - expected).abs() < 1e-12, "{:?} expected {:?}", drawn, expected);
+        }
+    }
+
+    #[test]
+    fn temperature_sharpens_and_flattens() {
+        let temperature = |temperature| EdgeWeighting { smoothing: Smoothing::None, temperature };
+        let scores = [(0.0, 1.0), (0.1, 3.0)];
+        assert_weights(weighted(&scores, temperature(1.0)), &[0.25, 0.75]);
+        assert_weights(weighted(&scores, temperature(0.5)), &[0.1, 0.9]);
+        let root = 3f64.sqrt();
+        assert_weights(weighted(&scores, temperature(2.0)), &[1.0 / (1.0 + root), root / (1.0 + root)]);
+        assert_weights(weighted(&scores, temperature(0.0)), &[0.0, 1.0]);
+        assert_weights(weighted(&scores, temperature(-1.0)), &[0.0, 1.0]);
+
+        // very large and very small scores sharpen the same as their ratio
+        let sharpened = 3f64.powi(10);
+        for scale in [1e200, 1e-200] {
+            let scores = [(0.0, scale), (0.1, 3.0 * scale)];
+            assert_weights(weighted(&scores, temperature(0.1)), &[1.0 / (1.0 + sharpened), sharpened / (1.0 + sharpened)]);
+        }
+    }
+
+    #[test]
+    fn smoothing_modes() {
+        let smoothing = |smoothing| EdgeWeighting { smoothing, temperature: 1.0 };
+        let scores = [(0.0, 1.0), (0.1, 3.0), (0.2, 0.0)];
+        assert_weights(weighted(&scores, smoothing(Smoothing::None)), &[0.25, 0.75, 0.0]);
+        assert_weights(weighted(&scores, smoothing(Smoothing::Additive { alpha: 1.0 })), &[2.0 / 7.0, 4.0 / 7.0, 1.0 / 7.0]);
+
+        // the second node is the only one that leads to 0.2, so every value is reached from one table
+        let mut first = DataNode::new(0.0);
+        first.forward_edges = edges(&scores);
+        let mut second = DataNode::new(0.1);
+        second.forward_edges = edges(&[(0.2, 2.0)]);
+        let mut nodes = vec![first, second];
+        DataNode::set_weights(&mut nodes, &smoothing(Smoothing::KneserNey { discount: 0.5 }));
+        assert_weights(weights(&nodes[0].forward_edges), &[5.0 / 24.0, 17.0 / 24.0, 2.0 / 24.0]);
+
+        // nothing observed falls back to uniform weights
+        assert_weights(weighted(&[(0.0, 0.0), (0.1, 0.0)], smoothing(Smoothing::None)), &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn edge_weighting_rejects_unusable_parameters() {
+        let smoothing = |smoothing| EdgeWeighting { smoothing, temperature: 1.0 };
+        assert!(EdgeWeighting::default().validate().is_ok());
+        assert!(smoothing(Smoothing::Additive { alpha: 0.0 }).validate().is_ok());
+        assert!(smoothing(Smoothing::Additive { alpha: -1.0 }).validate().is_err());
+        assert!(smoothing(Smoothing::Additive { alpha: f64::INFINITY }).validate().is_err());
+        assert!(smoothing(Smoothing::KneserNey { discount: 0.75 }).validate().is_ok());
+        assert!(smoothing(Smoothing::KneserNey { discount: -0.1 }).validate().is_err());
+        assert!(smoothing(Smoothing::KneserNey { discount: 1.5 }).validate().is_err());
+        assert!(EdgeWeighting { smoothing: Smoothing::None, temperature: f64::NAN }.validate().is_err());
+    }
+}
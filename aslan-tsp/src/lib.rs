@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+
+use aslan_data::{DataNode, Direction, EdgeWeighting};
+use serde::{Deserialize, Serialize};
+
+//route: an order in which to visit every node of the graph once, returning to the start
+//cost: price of stepping from one node to the next, cheap for likely or accurate transitions
+//the edges a good route uses are the connections worth strengthening
+
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum EdgeCost {
+    // one over the trained weight of the edge
+    InverseWeight,
+    // average distance between the edge's value and the moves that actually followed the node
+    HistoricalError,
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone)]
+pub struct RouteOptimizer {
+    pub cost: EdgeCost,
+    // number of nearest-neighbour routes built from evenly spread start nodes
+    pub starts: usize,
+    // maximum number of improvement passes of 2-opt and Or-opt
+    pub max_passes: usize,
+    // cost of stepping between two nodes with no edge, as a multiple of the most expensive edge
+    pub missing_edge_penalty: f64,
+    // share of a node's total score added to the edge its route leaves by
+    pub reinforcement: f64,
+}
+
+#[derive(Debug,Clone)]
+pub struct RouteResult {
+    pub nodes: Vec<DataNode>,
+    // node indexes in visiting order
+    pub route: Vec<usize>,
+    pub initial_cost: f64,
+    pub cost: f64,
+    pub passes: usize,
+}
+
+// smallest relative change counted as an improvement, keeps rounding noise from cycling moves
+const IMPROVEMENT_TOLERANCE: f64 = 1e-9;
+
+// sparse cost lookup between node indexes
+struct CostTable {
+    costs: Vec<HashMap<usize, f64>>,
+    missing_cost: f64,
+}
+
+impl Default for RouteOptimizer {
+    fn default() -> Self {
+        RouteOptimizer {
+            cost: EdgeCost::InverseWeight,
+            starts: 4,
+            max_passes: 20,
+            missing_edge_penalty: 10.0,
+            reinforcement: 0.1,
+        }
+    }
+}
+
+impl CostTable {
+    fn cost(&self, from: usize, to: usize) -> f64 {
+        *self.costs[from].get(&to).unwrap_or(&self.missing_cost)
+    }
+
+    fn route_cost(&self, route: &Vec<usize>) -> f64 {
+        (0..route.len()).map(|i| self.cost(route[i], route[(i + 1) % route.len()])).sum()
+    }
+}
+
+impl RouteOptimizer {
+    // data is the normalized series the model was trained on, only used for historical error costs
+    pub fn run(&self, nodes: &Vec<DataNode>, data: &Vec<f64>, weighting: &EdgeWeighting) -> RouteResult {
+        if nodes.len() < 2 {
+            return RouteResult { nodes: nodes.to_vec(), route: (0..nodes.len()).collect(), initial_cost: 0.0, cost: 0.0, passes: 0 };
+        }
+        let table = self.cost_table(nodes, data);
+
+        // best of several nearest-neighbour routes
+        let starts = self.starts.max(1).min(nodes.len());
+        let mut route = Vec::new();
+        let mut initial_cost = f64::MAX;
+        for start in 0..starts {
+            let candidate = RouteOptimizer::nearest_neighbour_route(&table, start * nodes.len() / starts);
+            let candidate_cost = table.route_cost(&candidate);
+            if candidate_cost < initial_cost {
+                initial_cost = candidate_cost;
+                route = candidate;
+            }
+        }
+
+        let mut passes = 0;
+        while passes < self.max_passes {
+            passes += 1;
+            let improved_two_opt = RouteOptimizer::two_opt(&table, &mut route);
+            let improved_or_opt = RouteOptimizer::or_opt(&table, &mut route);
+            if !improved_two_opt && !improved_or_opt {
+                break;
+            }
+        }
+
+        let mut refined = nodes.to_vec();
+        self.reinforce_route(&mut refined, &route);
+        DataNode::set_weights(&mut refined, weighting);
+
+        RouteResult {
+            cost: table.route_cost(&route),
+            nodes: refined,
+            route,
+            initial_cost,
+            passes,
+        }
+    }
+
+    fn cost_table(&self, nodes: &Vec<DataNode>, data: &Vec<f64>) -> CostTable {
+        let node_indexes: HashMap<String, usize> = nodes.iter().enumerate().map(|(i, x)| (x.average.to_string(), i)).collect();
+
+        // the moves that followed each node in the data
+        let mut followers: Vec<Vec<f64>> = vec![Vec::new(); nodes.len()];
+        if self.cost == EdgeCost::HistoricalError {
+            let (_, node_dict) = DataNode::init_map(nodes, &Vec::new());
+            for i in 1..data.len() {
+                let average = node_dict.get(&data[i - 1].to_string()).unwrap_or(&data[i - 1]);
+                if let Some(index) = node_indexes.get(&average.to_string()) {
+                    followers[*index].push(data[i]);
+                }
+            }
+        }
+
+        let mut costs: Vec<HashMap<usize, f64>> = Vec::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let mut node_costs = HashMap::new();
+            for edge in node.get_edges(Direction::Forward) {
+                let target = match node_indexes.get(&edge.value.to_string()) {
+                    Some(target) => *target,
+                    None => continue,
+                };
+                if target == index {
+                    continue;
+                }
+                let cost = match self.cost {
+                    EdgeCost::InverseWeight => {
+                        if edge.weight > 0.0 { 1.0 / edge.weight } else { continue }
+                    },
+                    EdgeCost::HistoricalError => {
+                        if followers[index].is_empty() {
+                            continue;
+                        }
+                        followers[index].iter().map(|x| (x - edge.value).abs()).sum::<f64>() / followers[index].len() as f64
+                    },
+                };
+                node_costs.insert(target, cost);
+            }
+            costs.push(node_costs);
+        }
+
+        let highest_cost = costs.iter().flat_map(|x| x.values()).cloned().fold(0.0, f64::max);
+        CostTable {
+            costs,
+            missing_cost: highest_cost.max(1.0) * self.missing_edge_penalty,
+        }
+    }
+
+    fn nearest_neighbour_route(table: &CostTable, start: usize) -> Vec<usize> {
+        let size = table.costs.len();
+        let mut visited = vec![false; size];
+        let mut route = vec![start];
+        visited[start] = true;
+        let mut current = start;
+
+        for _ in 1..size {
+            // prefer the cheapest connected node, fall back to the first unvisited one
+            let next = table.costs[current].iter()
+                .filter(|(target, _)| !visited[**target])
+                .min_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(b.0)))
+                .map(|(target, _)| *target)
+                .unwrap_or_else(|| visited.iter().position(|x| !x).unwrap());
+            visited[next] = true;
+            route.push(next);
+            current = next;
+        }
+        route
+    }
+
+    // reverses route segments while that makes the route cheaper
+    // costs are directed so the reversed segment is priced in its new direction
+    fn two_opt(table: &CostTable, route: &mut Vec<usize>) -> bool {
+        let size = route.len();
+        let mut improved = false;
+        if size < 4 {
+            return improved;
+        }
+
+        let mut i = 0;
+        while i < size - 2 {
+            // prefix sums of the segment costs walked forwards and backwards
+            let mut forward = vec![0.0; size];
+            let mut backward = vec![0.0; size];
+            for k in 1..size {
+                forward[k] = forward[k - 1] + table.cost(route[k - 1], route[k]);
+                backward[k] = backward[k - 1] + table.cost(route[k], route[k - 1]);
+            }
+
+            let mut reversed = false;
+            for j in i + 2..size {
+                let a = route[i];
+                let b = route[i + 1];
+                let c = route[j];
+                let d = route[(j + 1) % size];
+                if d == a {
+                    continue;
+                }
+                let before = table.cost(a, b) + (forward[j] - forward[i + 1]) + table.cost(c, d);
+                let after = table.cost(a, c) + (backward[j] - backward[i + 1]) + table.cost(b, d);
+                if after < before - IMPROVEMENT_TOLERANCE * before.abs().max(1.0) {
+                    route[i + 1..=j].reverse();
+                    improved = true;
+                    reversed = true;
+                    break;
+                }
+            }
+            if !reversed {
+                i += 1;
+            }
+        }
+        improved
+    }
+
+    // moves runs of one to three nodes to a cheaper position without reversing them
+    fn or_opt(table: &CostTable, route: &mut Vec<usize>) -> bool {
+        let mut improved = false;
+        for length in 1..=3 {
+            let mut start = 0;
+            while route.len() > length + 2 && start + length <= route.len() {
+                let size = route.len();
+                let end = start + length;
+                let previous = route[(start + size - 1) % size];
+                let first = route[start];
+                let last = route[end - 1];
+                let next = route[end % size];
+                let removed_gain = table.cost(previous, first) + table.cost(last, next) - table.cost(previous, next);
+
+                // try every route edge outside the segment and its two neighbouring edges
+                let mut best_position = None;
+                let mut best_gain = IMPROVEMENT_TOLERANCE * removed_gain.abs().max(1.0);
+                for position in 0..size {
+                    let following = (position + 1) % size;
+                    let touches_segment = (position + 1 >= start && position < end) || following == start;
+                    if touches_segment {
+                        continue;
+                    }
+                    let from = route[position];
+                    let to = route[following];
+                    let insert_cost = table.cost(from, first) + table.cost(last, to) - table.cost(from, to);
+                    if removed_gain - insert_cost > best_gain {
+                        best_gain = removed_gain - insert_cost;
+                        best_position = Some(position);
+                    }
+                }
+
+                match best_position {
+                    Some(position) => {
+                        let segment: Vec<usize> = route.drain(start..end).collect();
+                        let position = if position >= end { position - length } else { position };
+                        route.splice(position + 1..position + 1, segment);
+                        improved = true;
+                    },
+                    None => start += 1,
+                }
+            }
+        }
+        improved
+    }
+
+    // adds to the score of every edge the route travels along
+    fn reinforce_route(&self, nodes: &mut Vec<DataNode>, route: &Vec<usize>) {
+        for i in 0..route.len() {
+            let target = nodes[route[(i + 1) % route.len()]].average;
+            let edges = nodes[route[i]].get_edges_mut(Direction::Forward);
+            let total_score: f64 = edges.iter().map(|x| x.score).sum();
+            if let Some(edge) = edges.iter_mut().find(|x| x.value == target) {
+                edge.score += self.reinforcement * total_score;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aslan_data::Edge;
+
+    // a full asymmetric table, cost(i, j) and cost(j, i) differ for every pair
+    fn asymmetric_table(size: usize) -> CostTable {
+        let costs = (0..size).map(|from| {
+            (0..size).filter(|to| *to != from).map(|to| (to, ((from * 7 + to * 13) % 11 + 1) as f64)).collect()
+        }).collect();
+        CostTable { costs, missing_cost: 100.0 }
+    }
+
+    fn permutations(items: Vec<usize>) -> Vec<Vec<usize>> {
+        if items.len() <= 1 {
+            return vec![items];
+        }
+        let mut result = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            let mut rest = items.clone();
+            rest.remove(index);
+            for mut permutation in permutations(rest) {
+                permutation.insert(0, *item);
+                result.push(permutation);
+            }
+        }
+        result
+    }
+
+    fn assert_visits_every_node_once(route: &Vec<usize>, size: usize) {
+        let mut sorted = route.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..size).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn two_opt_never_increases_the_cost() {
+        let table = asymmetric_table(6);
+        for start in permutations((0..6).collect()) {
+            let mut route = start.clone();
+            RouteOptimizer::two_opt(&table, &mut route);
+            assert!(table.route_cost(&route) <= table.route_cost(&start), "{:?} became {:?}", start, route);
+            assert_visits_every_node_once(&route, 6);
+        }
+    }
+
+    #[test]
+    fn or_opt_never_increases_the_cost() {
+        let table = asymmetric_table(6);
+        for start in permutations((0..6).collect()) {
+            let mut route = start.clone();
+            RouteOptimizer::or_opt(&table, &mut route);
+            assert!(table.route_cost(&route) <= table.route_cost(&start), "{:?} became {:?}", start, route);
+            assert_visits_every_node_once(&route, 6);
+        }
+    }
+
+    #[test]
+    fn route_visits_every_node_once() {
+        // every node links to every other with a weight that differs by direction
+        let averages = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let nodes: Vec<DataNode> = averages.iter().enumerate().map(|(from, average)| {
+            let mut node = DataNode::new(*average);
+            for (to, target) in averages.iter().enumerate().filter(|(to, _)| *to != from) {
+                let mut edge = Edge::new(*target);
+                edge.score = 1.0;
+                edge.weight = ((from * 3 + to * 5) % 7 + 1) as f64 / 10.0;
+                node.forward_edges.push(edge);
+            }
+            node
+        }).collect();
+
+        let result = RouteOptimizer::default().run(&nodes, &Vec::new(), &EdgeWeighting::default());
+        assert_visits_every_node_once(&result.route, nodes.len());
+        assert!(result.cost <= result.initial_cost);
+        assert_eq!(result.nodes.len(), nodes.len());
+    }
+}
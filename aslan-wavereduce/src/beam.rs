@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use aslan_data::{DataNode, Direction};
+use serde::{Deserialize, Serialize};
+
+use crate::{WaveReduce, WaveReduceCell, WaveReduceResult, WaveReduceSolution};
+
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum GenerationMode {
+    // weighted random walks
+    Sample,
+    // the most probable paths, the same every time
+    // paths are ranked by log probability divided by length^length_penalty, so with a penalty
+    // above zero a path cut short at a node without edges is not favoured for being short
+    BeamSearch { beam_width: usize, length_penalty: f64 },
+}
+
+// a partial path kept in the beam
+#[derive(Debug,Clone)]
+struct Hypothesis {
+    node_index: usize,
+    states: Vec<f64>,
+    log_probability: f64,
+}
+
+impl Default for GenerationMode {
+    fn default() -> Self {
+        GenerationMode::Sample
+    }
+}
+
+impl Hypothesis {
+    fn score(&self, length_penalty: f64) -> f64 {
+        self.log_probability / (self.states.len() as f64).powf(length_penalty)
+    }
+}
+
+impl WaveReduce {
+    // returns the number_of_results most probable paths of number_of_cells cells
+    pub fn beam_search(self, data:&Vec<DataNode>, beam_width:usize, length_penalty:f64)->WaveReduceSolution{
+        let mut solution = WaveReduceSolution{
+            results:Vec::new(),
+        };
+        if data.is_empty() {
+            return solution;
+        }
+        let node_indexes: HashMap<String, usize> = data.iter().enumerate().map(|(i, x)| (x.average.to_string(), i)).collect();
+        // the beam has to hold at least as many paths as are returned
+        let beam_width = beam_width.max(self.number_of_results).max(1);
+
+        // with a history the beam starts from the nodes that best explain it
+        let mut beam = match self.start_weights(data) {
+            Some(weights) => {
+                let mut starts: Vec<Hypothesis> = weights.iter().enumerate().filter(|x| *x.1 > 0.0).map(|(node_index, weight)| Hypothesis {
+                    node_index,
+                    states: Vec::new(),
+                    log_probability: weight.ln(),
+                }).collect();
+                starts.sort_by(|a, b| b.log_probability.partial_cmp(&a.log_probability).unwrap_or(std::cmp::Ordering::Equal));
+                starts.truncate(beam_width);
+                starts
+            },
+            None => vec![Hypothesis {
+                node_index: WaveReduce::select_first_node_index(self.seed, data),
+                states: Vec::new(),
+                log_probability: 0.0,
+            }],
+        };
+        let mut finished = Vec::new();
+
+        for _ in 0..self.number_of_cells{
+            let mut candidates = Vec::new();
+            for hypothesis in beam.iter() {
+                let edges = data[hypothesis.node_index].get_edges(self.direction);
+                let total_weight:f64 = edges.iter().filter(|x| x.weight > 0.0 && x.weight.is_finite()).map(|x| x.weight).sum();
+                // a node without usable edges ends the path early
+                if !(total_weight > 0.0 && total_weight.is_finite()) {
+                    if !hypothesis.states.is_empty() {
+                        finished.push(hypothesis.clone());
+                    }
+                    continue;
+                }
+                for edge in edges.iter().filter(|x| x.weight > 0.0 && x.weight.is_finite()) {
+                    let mut states = hypothesis.states.clone();
+                    states.push(edge.value);
+                    candidates.push(Hypothesis {
+                        node_index: node_indexes.get(&edge.value.to_string()).copied().unwrap_or_else(|| WaveReduce::select_first_node_index(edge.value, data)),
+                        states,
+                        log_probability: hypothesis.log_probability + (edge.weight / total_weight).ln(),
+                    });
+                }
+            }
+            // the sort is stable so equally likely paths keep the order of the edges
+            candidates.sort_by(|a, b| b.log_probability.partial_cmp(&a.log_probability).unwrap_or(std::cmp::Ordering::Equal));
+            candidates.truncate(beam_width);
+            beam = candidates;
+            if beam.is_empty() {
+                break;
+            }
+        }
+        finished.extend(beam);
+
+        finished.sort_by(|a, b| b.score(length_penalty).partial_cmp(&a.score(length_penalty)).unwrap_or(std::cmp::Ordering::Equal));
+        for hypothesis in finished.into_iter().take(self.number_of_results) {
+            let mut result:Vec<WaveReduceCell> = hypothesis.states.iter().map(|x| WaveReduceCell { state: *x }).collect();
+            if self.direction == Direction::Backward {
+                result.reverse();
+            }
+            solution.results.push(WaveReduceResult {
+                result,
+                log_probability: Some(hypothesis.log_probability),
+            });
+        }
+        solution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aslan_data::Edge;
+
+    // nodes with the given averages and forward edges of the given weights
+    fn graph(nodes: &[(f64, &[(f64, f64)])]) -> Vec<DataNode> {
+        nodes.iter().map(|(average, edges)| {
+            let mut node = DataNode::new(*average);
+            for (value, weight) in edges.iter() {
+                let mut edge = Edge::new(*value);
+                edge.score = *weight;
+                edge.weight = *weight;
+                node.forward_edges.push(edge);
+            }
+            node
+        }).collect()
+    }
+
+    fn three_nodes() -> Vec<DataNode> {
+        graph(&[
+            (0.0, &[(0.0, 0.1), (1.0, 0.5), (2.0, 0.4)]),
+            (1.0, &[(0.0, 0.2), (1.0, 0.2), (2.0, 0.6)]),
+            (2.0, &[(0.0, 0.7), (1.0, 0.3)]),
+        ])
+    }
+
+    fn states(solution: &WaveReduceSolution) -> Vec<Vec<f64>> {
+        solution.results.iter().map(|x| x.result.iter().map(|cell| cell.state).collect()).collect()
+    }
+
+    #[test]
+    fn top_path_is_the_most_probable_one() {
+        let data = three_nodes();
+        // every path of three cells from the node at 0
+        let probability = |from: f64, to: f64| {
+            let node = data.iter().find(|x| x.average == from).unwrap();
+            node.forward_edges.iter().find(|x| x.value == to).map_or(0.0, |x| x.weight)
+        };
+        let mut best = (Vec::new(), 0.0);
+        for first in [0.0, 1.0, 2.0] {
+            for second in [0.0, 1.0, 2.0] {
+                for third in [0.0, 1.0, 2.0] {
+                    let path_probability = probability(0.0, first) * probability(first, second) * probability(second, third);
+                    if path_probability > best.1 {
+                        best = (vec![first, second, third], path_probability);
+                    }
+                }
+            }
+        }
+
+        let solution = WaveReduce::new(0.0, 3, 1).beam_search(&data, 9, 0.0);
+        assert_eq!(states(&solution)[0], best.0);
+        assert!((solution.results[0].log_probability.unwrap() - best.1.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn results_are_deterministic() {
+        let data = three_nodes();
+        let first = WaveReduce::new(0.0, 4, 5).beam_search(&data, 3, 0.5);
+        let second = WaveReduce::new(0.0, 4, 5).beam_search(&data, 3, 0.5);
+        assert_eq!(states(&first).len(), 5);
+        assert_eq!(states(&first), states(&second));
+        let log_probabilities: Vec<f64> = first.results.iter().map(|x| x.log_probability.unwrap()).collect();
+        assert!(log_probabilities.windows(2).all(|x| x[0] >= x[1]));
+    }
+
+    #[test]
+    fn length_penalty_ranks_full_paths_above_truncated_ones() {
+        // the likelier first move leads to 5, which has no edges and ends the path after one cell
+        let data = graph(&[
+            (0.0, &[(5.0, 0.6), (1.0, 0.4)]),
+            (1.0, &[(1.0, 1.0)]),
+            (5.0, &[]),
+        ]);
+        let unpenalized = WaveReduce::new(0.0, 3, 2).beam_search(&data, 2, 0.0);
+        assert_eq!(states(&unpenalized), vec![vec![5.0], vec![1.0, 1.0, 1.0]]);
+
+        let penalized = WaveReduce::new(0.0, 3, 2).beam_search(&data, 2, 1.0);
+        assert_eq!(states(&penalized), vec![vec![1.0, 1.0, 1.0], vec![5.0]]);
+    }
+}
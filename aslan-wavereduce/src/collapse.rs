@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+
+use aslan_data::{DataNode, Direction};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{history, WaveReduce, WaveReduceCell, WaveReduceResult, WaveReduceSolution};
+
+//domain: the nodes a cell can still collapse into
+//level: running total of the moves since the start of the constrained window
+//propagation removes every state that cannot be part of a path meeting all constraints,
+//the cell with the lowest entropy collapses next and a contradiction undoes the last collapse
+
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+pub enum Constraint {
+    // level after the given number of cells, e.g. a known closing value
+    Level { cell: usize, value: f64, tolerance: f64 },
+    // total of every move in the window
+    Sum { value: f64, tolerance: f64 },
+    // highest and lowest level reached within the window
+    High { value: f64, tolerance: f64 },
+    Low { value: f64, tolerance: f64 },
+    // distance between the highest and the lowest level
+    MaxRange { range: f64 },
+}
+
+// generates paths like WaveReduce, but only ones that satisfy every constraint
+#[derive(Debug)]
+pub struct WaveCollapse {
+    seed: f64,
+    number_of_cells: usize,
+    number_of_results: usize,
+    constraints: Vec<Constraint>,
+    // cells before the constrained window, levels are measured from the end of them
+    offset: usize,
+    // undone collapses allowed per result before it is given up on
+    max_backtracks: usize,
+    // the moves up to and including the seed, oldest first
+    history: Vec<f64>,
+}
+
+// bounds on the sum of the moves of the cells in start..end
+struct SegmentBound {
+    start: usize,
+    end: usize,
+    lower: f64,
+    upper: f64,
+}
+
+// the window has to reach a level above (or below) the value somewhere
+struct ReachBound {
+    value: f64,
+    above: bool,
+}
+
+struct CollapseState {
+    // successor node index and weight of every node
+    transitions: Vec<HashMap<usize, f64>>,
+    predecessors: Vec<Vec<usize>>,
+    first_node_index: usize,
+    segments: Vec<SegmentBound>,
+    reaches: Vec<ReachBound>,
+}
+
+// slack for rounding when comparing levels
+const LEVEL_EPSILON: f64 = 1e-9;
+
+impl Constraint {
+    // moves price levels to levels relative to the given base
+    pub fn shifted(&self, base: f64) -> Constraint {
+        match *self {
+            Constraint::Level { cell, value, tolerance } => Constraint::Level { cell, value: value - base, tolerance },
+            Constraint::High { value, tolerance } => Constraint::High { value: value - base, tolerance },
+            Constraint::Low { value, tolerance } => Constraint::Low { value: value - base, tolerance },
+            constraint => constraint,
+        }
+    }
+}
+
+impl WaveCollapse {
+    pub fn new(seed: f64, number_of_cells: usize, number_of_results: usize, constraints: Vec<Constraint>) -> Self {
+        WaveCollapse {
+            seed,
+            number_of_cells,
+            number_of_results,
+            constraints,
+            offset: 0,
+            max_backtracks: 1000,
+            history: Vec::new(),
+        }
+    }
+
+    // starts from the node that best explains the whole history instead of the node closest to the seed
+    pub fn with_history(mut self, history: Vec<f64>) -> Self {
+        self.history = history;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset.min(self.number_of_cells);
+        self
+    }
+
+    pub fn with_max_backtracks(mut self, max_backtracks: usize) -> Self {
+        self.max_backtracks = max_backtracks;
+        self
+    }
+
+    // results that could not be completed are left out, an unsatisfiable set of constraints gives none
+    pub fn generate_results<R: Rng + ?Sized>(&self, data: &Vec<DataNode>, rng: &mut R) -> WaveReduceSolution {
+        let mut solution = WaveReduceSolution {
+            results: Vec::new(),
+        };
+        if data.is_empty() || self.number_of_cells == 0 {
+            return solution;
+        }
+        let state = match self.collapse_state(data) {
+            Some(state) => state,
+            None => return solution,
+        };
+
+        // the propagated domains are the same for every result
+        let mut domains = self.initial_domains(&state, data.len());
+        if !self.propagate(&state, data, &mut domains) {
+            return solution;
+        }
+
+        for _ in 0..self.number_of_results {
+            if let Some(path) = self.collapse(&state, data, domains.clone(), rng) {
+                solution.results.push(WaveReduceResult {
+                    result: path.iter().map(|x| WaveReduceCell { state: data[*x].average }).collect(),
+                    log_probability: None,
+                });
+            }
+        }
+        solution
+    }
+
+    fn collapse_state(&self, data: &Vec<DataNode>) -> Option<CollapseState> {
+        let node_indexes: HashMap<String, usize> = data.iter().enumerate().map(|(i, x)| (x.average.to_string(), i)).collect();
+        let transitions: Vec<HashMap<usize, f64>> = data.iter().map(|node| {
+            let mut successors = HashMap::new();
+            for edge in node.get_edges(Direction::Forward) {
+                let target = match node_indexes.get(&edge.value.to_string()) {
+                    Some(target) => *target,
+                    None => WaveReduce::select_first_node_index(edge.value, data),
+                };
+                let weight = if edge.weight.is_finite() { edge.weight.max(0.0) } else { 0.0 };
+                *successors.entry(target).or_insert(0.0) += weight;
+            }
+            successors
+        }).collect();
+        let mut predecessors = vec![Vec::new(); data.len()];
+        for (index, successors) in transitions.iter().enumerate() {
+            for target in successors.keys() {
+                predecessors[*target].push(index);
+            }
+        }
+
+        let window = self.number_of_cells - self.offset;
+        let mut segments = Vec::new();
+        let mut reaches = Vec::new();
+        for constraint in self.constraints.iter() {
+            match *constraint {
+                Constraint::Level { cell, value, tolerance } => {
+                    if cell > window {
+                        return None;
+                    }
+                    segments.push(self.segment(0, cell, value - tolerance, value + tolerance));
+                },
+                Constraint::Sum { value, tolerance } => {
+                    segments.push(self.segment(0, window, value - tolerance, value + tolerance));
+                },
+                Constraint::High { value, tolerance } => {
+                    for cell in 0..=window {
+                        segments.push(self.segment(0, cell, f64::MIN, value + tolerance));
+                    }
+                    reaches.push(ReachBound { value: value - tolerance, above: true });
+                },
+                Constraint::Low { value, tolerance } => {
+                    for cell in 0..=window {
+                        segments.push(self.segment(0, cell, value - tolerance, f64::MAX));
+                    }
+                    reaches.push(ReachBound { value: value + tolerance, above: false });
+                },
+                Constraint::MaxRange { range } => {
+                    for start in 0..window {
+                        for end in start + 1..=window {
+                            segments.push(self.segment(start, end, -range, range));
+                        }
+                    }
+                },
+            }
+        }
+
+        Some(CollapseState {
+            transitions,
+            predecessors,
+            first_node_index: self.first_node_index(data),
+            segments,
+            reaches,
+        })
+    }
+
+    fn first_node_index(&self, data: &Vec<DataNode>) -> usize {
+        match history::start_weights(data, &self.history, Direction::Forward) {
+            Some(weights) => (0..weights.len()).fold(0, |best, x| if weights[x] > weights[best] { x } else { best }),
+            None => WaveReduce::select_first_node_index(self.seed, data),
+        }
+    }
+
+    // window cells to path cells
+    fn segment(&self, start: usize, end: usize, lower: f64, upper: f64) -> SegmentBound {
+        SegmentBound { start: self.offset + start, end: self.offset + end, lower, upper }
+    }
+
+    fn initial_domains(&self, state: &CollapseState, size: usize) -> Vec<Vec<usize>> {
+        let mut reachable = vec![false; size];
+        for successors in state.transitions.iter() {
+            for target in successors.keys() {
+                reachable[*target] = true;
+            }
+        }
+        let all_states: Vec<usize> = (0..size).filter(|x| reachable[*x]).collect();
+        vec![all_states; self.number_of_cells]
+    }
+
+    // removes states until the domains are consistent, false on a contradiction
+    fn propagate(&self, state: &CollapseState, data: &Vec<DataNode>, domains: &mut Vec<Vec<usize>>) -> bool {
+        let size = domains.len();
+        let mut marked = vec![false; data.len()];
+        loop {
+            let mut changed = false;
+
+            // every state needs a predecessor ...
+            for cell in 0..size {
+                let before = domains[cell].len();
+                marked.iter_mut().for_each(|x| *x = false);
+                let previous_states = if cell == 0 { vec![state.first_node_index] } else { domains[cell - 1].clone() };
+                for previous in previous_states {
+                    for target in state.transitions[previous].keys() {
+                        marked[*target] = true;
+                    }
+                }
+                domains[cell].retain(|x| marked[*x]);
+                changed |= domains[cell].len() != before;
+            }
+            // ... and a successor
+            for cell in (0..size - 1).rev() {
+                let before = domains[cell].len();
+                marked.iter_mut().for_each(|x| *x = false);
+                for next in domains[cell + 1].iter() {
+                    for previous in state.predecessors[*next].iter() {
+                        marked[*previous] = true;
+                    }
+                }
+                domains[cell].retain(|x| marked[*x]);
+                changed |= domains[cell].len() != before;
+            }
+            if domains.iter().any(|x| x.is_empty()) {
+                return false;
+            }
+
+            // smallest and largest move of every cell and their running totals
+            let minimums: Vec<f64> = domains.iter().map(|x| x.iter().map(|s| data[*s].average).fold(f64::MAX, f64::min)).collect();
+            let maximums: Vec<f64> = domains.iter().map(|x| x.iter().map(|s| data[*s].average).fold(f64::MIN, f64::max)).collect();
+            let mut lowest = vec![0.0; size + 1];
+            let mut highest = vec![0.0; size + 1];
+            for cell in 0..size {
+                lowest[cell + 1] = lowest[cell] + minimums[cell];
+                highest[cell + 1] = highest[cell] + maximums[cell];
+            }
+
+            for reach in state.reaches.iter() {
+                let reachable = (self.offset..=size).any(|end| {
+                    if reach.above {
+                        highest[end] - highest[self.offset] >= reach.value - LEVEL_EPSILON
+                    } else {
+                        lowest[end] - lowest[self.offset] <= reach.value + LEVEL_EPSILON
+                    }
+                });
+                if !reachable {
+                    return false;
+                }
+            }
+
+            for segment in state.segments.iter() {
+                let segment_lowest = lowest[segment.end] - lowest[segment.start];
+                let segment_highest = highest[segment.end] - highest[segment.start];
+                if segment_lowest > segment.upper + LEVEL_EPSILON || segment_highest < segment.lower - LEVEL_EPSILON {
+                    return false;
+                }
+                // a state is kept if the rest of the segment can still make up the difference
+                for cell in segment.start..segment.end {
+                    let before = domains[cell].len();
+                    let rest_lowest = segment_lowest - minimums[cell];
+                    let rest_highest = segment_highest - maximums[cell];
+                    domains[cell].retain(|x| {
+                        let value = data[*x].average;
+                        rest_lowest + value <= segment.upper + LEVEL_EPSILON && rest_highest + value >= segment.lower - LEVEL_EPSILON
+                    });
+                    if domains[cell].is_empty() {
+                        return false;
+                    }
+                    changed |= domains[cell].len() != before;
+                }
+            }
+
+            if !changed {
+                return true;
+            }
+        }
+    }
+
+    // collapses cells until every one holds a single state, none when it runs out of backtracks
+    fn collapse<R: Rng + ?Sized>(&self, state: &CollapseState, data: &Vec<DataNode>, mut domains: Vec<Vec<usize>>, rng: &mut R) -> Option<Vec<usize>> {
+        let mut history: Vec<(Vec<Vec<usize>>, usize, usize)> = Vec::new();
+        let mut backtracks = 0;
+
+        loop {
+            let weights = WaveCollapse::state_weights(state, &domains);
+            let cell = match WaveCollapse::lowest_entropy_cell(&domains, &weights) {
+                Some(cell) => cell,
+                None => return Some(domains.iter().map(|x| x[0]).collect()),
+            };
+            let chosen = domains[cell][WaveCollapse::choose(&weights[cell], rng)];
+
+            history.push((domains.clone(), cell, chosen));
+            domains[cell] = vec![chosen];
+
+            // undo collapses until the remaining domains are consistent again
+            let mut consistent = self.propagate(state, data, &mut domains);
+            while !consistent {
+                let (previous, cell, chosen) = history.pop()?;
+                backtracks += 1;
+                if backtracks > self.max_backtracks {
+                    return None;
+                }
+                domains = previous;
+                domains[cell].retain(|x| *x != chosen);
+                consistent = !domains[cell].is_empty() && self.propagate(state, data, &mut domains);
+            }
+        }
+    }
+
+    // weight of every state of every cell, summed over the states that can lead to it
+    fn state_weights(state: &CollapseState, domains: &Vec<Vec<usize>>) -> Vec<Vec<f64>> {
+        let mut totals = vec![0.0; state.transitions.len()];
+        (0..domains.len()).map(|cell| {
+            totals.iter_mut().for_each(|x| *x = 0.0);
+            let previous_states = if cell == 0 { vec![state.first_node_index] } else { domains[cell - 1].clone() };
+            for previous in previous_states {
+                for (target, weight) in state.transitions[previous].iter() {
+                    totals[*target] += weight;
+                }
+            }
+            domains[cell].iter().map(|x| totals[*x]).collect()
+        }).collect()
+    }
+
+    fn lowest_entropy_cell(domains: &Vec<Vec<usize>>, weights: &Vec<Vec<f64>>) -> Option<usize> {
+        let mut lowest: Option<(usize, f64)> = None;
+        for cell in 0..domains.len() {
+            if domains[cell].len() < 2 {
+                continue;
+            }
+            let total: f64 = weights[cell].iter().sum();
+            let entropy = if total > 0.0 && total.is_finite() {
+                -weights[cell].iter().filter(|x| **x > 0.0).map(|x| (x / total) * (x / total).ln()).sum::<f64>()
+            } else {
+                (domains[cell].len() as f64).ln()
+            };
+            if lowest.map_or(true, |x| entropy < x.1) {
+                lowest = Some((cell, entropy));
+            }
+        }
+        lowest.map(|x| x.0)
+    }
+
+    fn choose<R: Rng + ?Sized>(weights: &Vec<f64>, rng: &mut R) -> usize {
+        let total: f64 = weights.iter().sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return rng.gen_range(0..weights.len());
+        }
+        let mut random_number = rng.gen_range(0.0..total);
+        for (index, weight) in weights.iter().enumerate() {
+            random_number -= weight;
+            if random_number <= 0.0 {
+                return index;
+            }
+        }
+        weights.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aslan_data::Edge;
+
+    // nodes with the given averages and forward edges of weight one to the listed targets
+    fn graph(nodes: &[(f64, &[f64])]) -> Vec<DataNode> {
+        nodes.iter().map(|(average, targets)| {
+            let mut node = DataNode::new(*average);
+            for target in targets.iter() {
+                let mut edge = Edge::new(*target);
+                edge.score = 1.0;
+                edge.weight = 1.0;
+                node.forward_edges.push(edge);
+            }
+            node
+        }).collect()
+    }
+
+    // every move can follow every other
+    fn complete_graph() -> Vec<DataNode> {
+        let moves = [-1.0, 0.0, 1.0];
+        graph(&moves.iter().map(|x| (*x, &moves[..])).collect::<Vec<(f64, &[f64])>>())
+    }
+
+    fn paths(collapse: &WaveCollapse, data: &Vec<DataNode>) -> Vec<Vec<f64>> {
+        let mut rng = StdRng::seed_from_u64(3);
+        collapse.generate_results(data, &mut rng).results.iter()
+            .map(|x| x.result.iter().map(|cell| cell.state).collect())
+            .collect()
+    }
+
+    fn levels(path: &Vec<f64>) -> Vec<f64> {
+        let mut level = 0.0;
+        let mut levels = vec![level];
+        for value in path {
+            level += value;
+            levels.push(level);
+        }
+        levels
+    }
+
+    #[test]
+    fn every_path_meets_a_level() {
+        let data = complete_graph();
+        let collapse = WaveCollapse::new(0.0, 5, 20, vec![Constraint::Level { cell: 3, value: 2.0, tolerance: 0.1 }]);
+        let results = paths(&collapse, &data);
+        assert_eq!(results.len(), 20);
+        for path in results {
+            assert_eq!(path.len(), 5);
+            assert!((levels(&path)[3] - 2.0).abs() <= 0.1, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn unsatisfiable_constraints_give_no_results() {
+        let data = complete_graph();
+        assert!(paths(&WaveCollapse::new(0.0, 4, 5, vec![Constraint::Sum { value: 10.0, tolerance: 0.5 }]), &data).is_empty());
+        assert!(paths(&WaveCollapse::new(0.0, 4, 5, vec![Constraint::Level { cell: 5, value: 0.0, tolerance: 0.5 }]), &data).is_empty());
+        let contradicting = vec![Constraint::High { value: 1.0, tolerance: 0.0 }, Constraint::Level { cell: 2, value: 2.0, tolerance: 0.0 }];
+        assert!(paths(&WaveCollapse::new(0.0, 4, 5, contradicting), &data).is_empty());
+    }
+
+    #[test]
+    fn range_and_extremes_bound_every_path() {
+        let data = complete_graph();
+        let collapse = WaveCollapse::new(0.0, 6, 20, vec![Constraint::MaxRange { range: 1.0 }]);
+        let results = paths(&collapse, &data);
+        assert_eq!(results.len(), 20);
+        for path in results {
+            let levels = levels(&path);
+            let high = levels.iter().cloned().fold(f64::MIN, f64::max);
+            let low = levels.iter().cloned().fold(f64::MAX, f64::min);
+            assert!(high - low <= 1.0, "{:?}", path);
+        }
+
+        let bounds = vec![Constraint::High { value: 2.0, tolerance: 0.0 }, Constraint::Low { value: -1.0, tolerance: 0.0 }];
+        let results = paths(&WaveCollapse::new(0.0, 6, 20, bounds), &data);
+        assert_eq!(results.len(), 20);
+        for path in results {
+            let levels = levels(&path);
+            assert_eq!(levels.iter().cloned().fold(f64::MIN, f64::max), 2.0, "{:?}", path);
+            assert_eq!(levels.iter().cloned().fold(f64::MAX, f64::min), -1.0, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_backtracks() {
+        // from 0 the walk goes to 1 or -1, 1 can only repeat itself and -1 can only move 0.5
+        // so a sum of -0.5 rules out 1, but only once the second cell is known
+        let data = graph(&[(0.0, &[1.0, -1.0]), (1.0, &[1.0]), (-1.0, &[-1.0, 0.5]), (0.5, &[0.5])]);
+        let constraints = vec![Constraint::Sum { value: -0.5, tolerance: 0.6 }];
+
+        let results = paths(&WaveCollapse::new(0.0, 2, 40, constraints.clone()), &data);
+        assert_eq!(results.len(), 40);
+        assert!(results.iter().all(|x| *x == vec![-1.0, 0.5]));
+
+        let limited = paths(&WaveCollapse::new(0.0, 2, 40, constraints).with_max_backtracks(0), &data);
+        assert!(!limited.is_empty() && limited.len() < 40, "{}", limited.len());
+        assert!(limited.iter().all(|x| *x == vec![-1.0, 0.5]));
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use aslan_data::{DataNode, Direction};
+use rand::prelude::*;
+
+use crate::WaveReduce;
+
+//history: the moves that led up to the seed, oldest first
+//the graph is read as a hidden markov chain, every node emits moves close to its average,
+//and the forward algorithm gives how likely every node is to be the current state after the history
+
+// how likely every node is to be where the walk starts, none without a history to condition on
+pub(crate) fn start_weights(data: &Vec<DataNode>, history: &Vec<f64>, direction: Direction) -> Option<Vec<f64>> {
+    if history.len() < 2 || data.is_empty() {
+        return None;
+    }
+    // the walk reads the history towards the seed, backwards it starts from the oldest move
+    let observations: Vec<f64> = match direction {
+        Direction::Forward => history.to_vec(),
+        Direction::Backward => history.iter().rev().cloned().collect(),
+    };
+
+    let transitions = transition_probabilities(data, direction);
+    let sigma = emission_width(data);
+
+    let mut weights = emissions(data, observations[0], sigma);
+    for observation in observations.iter().skip(1) {
+        let mut predicted = vec![0.0; data.len()];
+        for (index, weight) in weights.iter().enumerate() {
+            if *weight == 0.0 {
+                continue;
+            }
+            for (target, probability) in transitions[index].iter() {
+                predicted[*target] += weight * probability;
+            }
+        }
+        let emitted = emissions(data, *observation, sigma);
+        weights = predicted.iter().zip(emitted.iter()).map(|(a, b)| a * b).collect();
+
+        // the model has never seen this sequence, start over from the latest move
+        if !normalize(&mut weights) {
+            weights = emitted;
+        }
+    }
+    Some(weights)
+}
+
+pub(crate) fn weighted_index<R: Rng + ?Sized>(weights: &Vec<f64>, rng: &mut R) -> usize {
+    let total: f64 = weights.iter().sum();
+    if !(total > 0.0 && total.is_finite()) {
+        return rng.gen_range(0..weights.len());
+    }
+    let mut random_number = rng.gen_range(0.0..total);
+    for (index, weight) in weights.iter().enumerate() {
+        random_number -= weight;
+        if random_number <= 0.0 {
+            return index;
+        }
+    }
+    weights.len() - 1
+}
+
+fn transition_probabilities(data: &Vec<DataNode>, direction: Direction) -> Vec<Vec<(usize, f64)>> {
+    let node_indexes: HashMap<String, usize> = data.iter().enumerate().map(|(i, x)| (x.average.to_string(), i)).collect();
+    data.iter().map(|node| {
+        let edges = node.get_edges(direction);
+        let total: f64 = edges.iter().filter(|x| x.weight > 0.0 && x.weight.is_finite()).map(|x| x.weight).sum();
+        edges.iter().map(|edge| {
+            let target = node_indexes.get(&edge.value.to_string()).copied().unwrap_or_else(|| WaveReduce::select_first_node_index(edge.value, data));
+            let probability = if total > 0.0 && total.is_finite() {
+                if edge.weight > 0.0 && edge.weight.is_finite() { edge.weight / total } else { 0.0 }
+            } else {
+                1.0 / edges.len() as f64
+            };
+            (target, probability)
+        }).collect()
+    }).collect()
+}
+
+// likelihood of a move under every node, normalized
+fn emissions(data: &Vec<DataNode>, observation: f64, sigma: f64) -> Vec<f64> {
+    let mut emitted: Vec<f64> = data.iter().map(|x| (-(x.average - observation).powi(2) / (2.0 * sigma * sigma)).exp()).collect();
+    // too far from every node for the kernel, fall back to the closest one
+    if !normalize(&mut emitted) {
+        emitted = vec![0.0; data.len()];
+        emitted[WaveReduce::select_first_node_index(observation, data)] = 1.0;
+    }
+    emitted
+}
+
+// half of the median gap between neighbouring nodes, so a move mostly belongs to its closest node
+fn emission_width(data: &Vec<DataNode>) -> f64 {
+    let mut averages: Vec<f64> = data.iter().map(|x| x.average).collect();
+    averages.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mut gaps: Vec<f64> = averages.windows(2).map(|x| x[1] - x[0]).filter(|x| *x > 0.0).collect();
+    if gaps.is_empty() {
+        return 1.0;
+    }
+    gaps.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    gaps[gaps.len() / 2] / 2.0
+}
+
+fn normalize(weights: &mut Vec<f64>) -> bool {
+    let total: f64 = weights.iter().sum();
+    if !(total > 0.0 && total.is_finite()) {
+        return false;
+    }
+    weights.iter_mut().for_each(|x| *x /= total);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aslan_data::Edge;
+
+    // nodes with the given averages and forward edges of weight one to the listed targets
+    fn graph(nodes: &[(f64, &[f64])]) -> Vec<DataNode> {
+        nodes.iter().map(|(average, targets)| {
+            let mut node = DataNode::new(*average);
+            for target in targets.iter() {
+                let mut edge = Edge::new(*target);
+                edge.score = 1.0;
+                edge.weight = 1.0;
+                node.forward_edges.push(edge);
+            }
+            node
+        }).collect()
+    }
+
+    #[test]
+    fn history_picks_between_nodes_as_close_to_the_seed() {
+        // -0.5 and 0.5 are equally close to the seed 0, but only 0.5 follows the move of 2 before it
+        let data = graph(&[(-0.5, &[-2.0]), (0.5, &[2.0]), (2.0, &[0.5]), (-2.0, &[-0.5])]);
+        let weights = start_weights(&data, &vec![2.0, 0.0], Direction::Forward).unwrap();
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(weights[1] > 0.99, "{:?}", weights);
+        assert!(weights[0] < 0.01, "{:?}", weights);
+
+        // without a history there is nothing to weigh
+        assert!(start_weights(&data, &vec![0.0], Direction::Forward).is_none());
+    }
+
+    #[test]
+    fn unseen_history_starts_over_from_the_latest_move() {
+        // 1000 only leads back to itself, so no node can follow it with a move of 0
+        let data = graph(&[(0.0, &[1.0]), (1.0, &[2.0]), (2.0, &[0.0]), (1000.0, &[1000.0])]);
+        let weights = start_weights(&data, &vec![1000.0, 0.0], Direction::Forward).unwrap();
+        assert_eq!(weights, emissions(&data, 0.0, emission_width(&data)));
+        assert_eq!(weights[3], 0.0);
+        assert!(weights[0] > weights[1] && weights[1] > weights[2]);
+    }
+}
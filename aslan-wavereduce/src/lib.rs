@@ -1,35 +1,63 @@
-use aslan_data::{self, Node,NodeSet, DataNode, Edge};
+use aslan_data::{self, Node,NodeSet, DataNode, Direction};
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+mod parallel;
+mod collapse;
+mod beam;
+mod scoring;
+mod stream;
+mod history;
+mod sampling;
+pub use parallel::WaveReducePool;
+pub use collapse::{Constraint, WaveCollapse};
+pub use beam::GenerationMode;
+pub use scoring::{ResultScorer, ScoringMetric};
+pub use stream::WavePaths;
+pub use sampling::SamplingStrategy;
+
 //seed: some inital data for the graph
 //entropy: possible states of the each cell
 //cell: contains a state
 //result: a generated solution
 
-#[derive(Debug)]
+#[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct WaveReduce{
     seed:f64,
     number_of_cells:usize,
     number_of_results:usize,
+    #[serde(default)]
+    direction:Direction,
+    // the moves up to and including the seed, oldest first
+    #[serde(default)]
+    history:Vec<f64>,
+    #[serde(default)]
+    sampling:SamplingStrategy,
 }
-#[derive(Debug)]
+#[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct WaveReduceCell{
     pub state:f64,
 }
-#[derive(Debug)]
+#[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct WaveReduceResult{
     pub result:Vec<WaveReduceCell>,
+    // natural log of the probability of the path, only known for beam search results
+    #[serde(default)]
+    pub log_probability:Option<f64>,
 }
-#[derive(Debug)]
+#[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct WaveReduceSolution{
     pub results:Vec<WaveReduceResult>,
 }
 
-#[derive(Debug)]
+#[derive(Debug,Serialize, Deserialize,Clone)]
 pub struct WaveReduceSummary{
-    partition_total:f64,
-    solution_total:f64,
-    difference:f64,
-    solution_index:usize,
+    pub partition_total:f64,
+    pub solution_total:f64,
+    pub difference:f64,
+    // what the results are ranked by, lower is better
+    pub score:f64,
+    pub solution_index:usize,
 }
 
 impl WaveReduce {
@@ -38,6 +66,40 @@ impl WaveReduce {
             seed,
             number_of_cells,
             number_of_results,
+            direction:Direction::Forward,
+            history:Vec::new(),
+            sampling:SamplingStrategy::default(),
+        }
+    }
+
+    // walk the graph backwards from the seed to generate the values that came before it
+    pub fn with_direction(mut self, direction:Direction)->Self{
+        self.direction = direction;
+        self
+    }
+
+    // walks start from the nodes that best explain the whole history instead of the node closest to the seed
+    // the seed should be the newest move of the history, or the oldest one when walking backwards
+    pub fn with_history(mut self, history:Vec<f64>)->Self{
+        self.history = history;
+        self
+    }
+
+    // beam search ranks every edge and ignores the sampling strategy
+    pub fn with_sampling(mut self, sampling:SamplingStrategy)->Self{
+        self.sampling = sampling;
+        self
+    }
+
+    fn start_weights(&self, data:&Vec<DataNode>)->Option<Vec<f64>>{
+        history::start_weights(data, &self.history, self.direction)
+    }
+
+    // draws a start node when there is a history, otherwise every walk starts next to the seed
+    fn start_node_index<R: Rng + ?Sized>(first_node_index:usize, start_weights:&Option<Vec<f64>>, rng:&mut R)->usize{
+        match start_weights {
+            Some(weights) => history::weighted_index(weights, rng),
+            None => first_node_index,
         }
     }
     
@@ -50,7 +112,7 @@ impl WaveReduce {
     //fuzzy search gives a range of values close to the search value
     fn fuzzy_search(search:f64,data:&Vec<DataNode>)->usize{
         let mut index = 0;
-        let mut previous_diff = 0.0;
+        let mut previous_diff = f64::MAX;
         for i in 0..data.len(){
             let difference = (data[i].average - search).abs();
             if difference < previous_diff{
@@ -62,55 +124,58 @@ impl WaveReduce {
     }
 
     // add function to fuzzy search for the closest match for the seed
-    pub fn generate_results(self ,data:&Vec<DataNode>)->WaveReduceSolution{
+    // backward results are returned in chronological order, ending next to the seed
+    // the same rng state always walks the same paths
+    pub fn generate_results<R: Rng + ?Sized>(self ,data:&Vec<DataNode>, rng:&mut R)->WaveReduceSolution{
         let mut solution = WaveReduceSolution{
             results:Vec::new(),
         };
-        let mut selected_node_index  = WaveReduce::select_first_node_index(self.seed,data);
-        let mut selected_node = &data[selected_node_index];
+        let first_node_index  = WaveReduce::select_first_node_index(self.seed,data);
+        let start_weights = self.start_weights(data);
         //for loop to generate results
         for _ in 0..self.number_of_results{
-            let mut wave_result = WaveReduceResult{
-                result:Vec::new(),
-            };
-            for _ in 0..self.number_of_cells{
-                //randomly select a node from the selected node's neighbors
-                let edge_index = WaveReduce::get_weighted_random(&selected_node.edges);
-
-                let cell = WaveReduceCell{
-                    state:selected_node.edges[edge_index].value,
-                };
-
-                let new_seed = selected_node.edges[edge_index].value;
-                selected_node_index  = WaveReduce::select_first_node_index(new_seed,data);
-                selected_node = &data[selected_node_index];
-
-                wave_result.result.push(cell);
-            }
-
-            solution.results.push(wave_result);
+            let start_node_index = WaveReduce::start_node_index(first_node_index, &start_weights, rng);
+            solution.results.push(self.generate_result(data, start_node_index, rng));
         }
         
 
         solution
     }
 
-    fn get_weighted_random(edges: &Vec<Edge>)->usize{
-        let mut rng = rand::thread_rng();
-        let mut total_weight:f64 = 0.0;
-        for edge in edges{
-            total_weight += edge.weight;
+    // every result starts its walk from the seed
+    fn generate_result<R: Rng + ?Sized>(&self, data:&Vec<DataNode>, first_node_index:usize, rng:&mut R)->WaveReduceResult{
+        let mut states = Vec::with_capacity(self.number_of_cells);
+        self.walk_into(data, first_node_index, rng, &mut states);
+        WaveReduceResult{
+            result:states.into_iter().map(|state| WaveReduceCell{ state }).collect(),
+            log_probability:None,
         }
-        let mut random_number:f64 = rng.gen_range(0.0..total_weight);
-        let mut index = 0;
-        for edge in edges{
-            random_number -= edge.weight;
-            if random_number <= 0.0{
-                break;
-            }
-            index += 1;
+    }
+
+    // walks from the seed and writes the states into the buffer, replacing what it held
+    fn walk_into<R: Rng + ?Sized>(&self, data:&Vec<DataNode>, first_node_index:usize, rng:&mut R, buffer:&mut Vec<f64>){
+        buffer.clear();
+        let mut selected_node = &data[first_node_index];
+        for _ in 0..self.number_of_cells{
+            //randomly select a node from the selected node's neighbors
+            let edges = selected_node.get_edges(self.direction);
+            let previous = if buffer.len() >= 2 { Some(buffer[buffer.len() - 2]) } else { None };
+            // a node without edges ends the walk early, as in beam search
+            let edge_index = match self.sampling.choose(selected_node, self.direction, previous, rng) {
+                Some(edge_index) => edge_index,
+                None => break,
+            };
+
+            let new_seed = edges[edge_index].value;
+            let selected_node_index  = WaveReduce::select_first_node_index(new_seed,data);
+            selected_node = &data[selected_node_index];
+
+            buffer.push(new_seed);
+        }
+
+        if self.direction == Direction::Backward {
+            buffer.reverse();
         }
-        index
     }
     
 }
@@ -126,7 +191,7 @@ impl WaveReduceSolution {
         flattened_results
     }
 
-    pub fn get_result_summary(&self, partition:&Vec<f64>)->Vec<WaveReduceSummary>{
+    pub fn get_result_summary(&self, partition:&Vec<f64>, scorer:&dyn ResultScorer)->Vec<WaveReduceSummary>{
         let mut summary = Vec::new();
         //add up data in partitions
         let sum = partition.iter().fold(0.0, |sum, x| sum + x);
@@ -141,6 +206,7 @@ impl WaveReduceSolution {
                 partition_total:sum,
                 solution_total:result_sum,
                 difference,
+                score:scorer.score(&flattened_result, partition),
                 solution_index:index,
             };
 
@@ -152,17 +218,15 @@ impl WaveReduceSolution {
     pub fn get_top_results(&self, summary:Vec<WaveReduceSummary>, number_of_results:usize)->Vec<&WaveReduceResult>{
         let mut top_results = Vec::new();
         let mut sorted_summary = summary;
-        sorted_summary.sort_by(|a, b| a.difference.partial_cmp(&b.difference).unwrap());
-        for i in 0..number_of_results{
-            let index = sorted_summary[i].solution_index;
-            top_results.push(&self.results[index]);
+        sorted_summary.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(std::cmp::Ordering::Equal));
+        for summary_item in sorted_summary.iter().take(number_of_results){
+            top_results.push(&self.results[summary_item.solution_index]);
         }
         top_results
     }
 
-    pub fn get_random_results(&self, number_of_results:usize)->Vec<&WaveReduceResult>{
+    pub fn get_random_results<R: Rng + ?Sized>(&self, number_of_results:usize, rng:&mut R)->Vec<&WaveReduceResult>{
         let mut random_results = Vec::new();
-        let mut rng = rand::thread_rng();
         for _ in 0..number_of_results{
             let index = rng.gen_range(0..self.results.len());
             random_results.push(&self.results[index]);
@@ -0,0 +1,47 @@
+use aslan_data::DataNode;
+use rand::{prelude::*, rngs::StdRng};
+use rayon::prelude::*;
+
+use crate::{WaveReduce, WaveReduceSolution};
+
+// generates the results of a wavereduce on a thread pool
+// every result walks with its own rng stream seeded from the run seed, so a fixed seed
+// gives the same solution no matter how many threads there are or how the work is split
+// building a pool starts its threads, so build one and share it, and call it from a thread that may block
+pub struct WaveReducePool {
+    pool: rayon::ThreadPool,
+}
+
+impl WaveReducePool {
+    // zero threads uses one thread per cpu
+    pub fn new(threads: usize) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("wavereduce-{}", index))
+            .build()
+            .unwrap();
+        WaveReducePool { pool }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    pub fn generate_results(&self, wavereduce: WaveReduce, data: &Vec<DataNode>, rng_seed: u64) -> WaveReduceSolution {
+        let first_node_index = WaveReduce::select_first_node_index(wavereduce.seed, data);
+        let start_weights = wavereduce.start_weights(data);
+
+        let mut seed_rng = StdRng::seed_from_u64(rng_seed);
+        let result_seeds: Vec<u64> = (0..wavereduce.number_of_results).map(|_| seed_rng.gen()).collect();
+
+        let results = self.pool.install(|| {
+            result_seeds.par_iter().map(|result_seed| {
+                let mut rng = StdRng::seed_from_u64(*result_seed);
+                let start_node_index = WaveReduce::start_node_index(first_node_index, &start_weights, &mut rng);
+                wavereduce.generate_result(data, start_node_index, &mut rng)
+            }).collect()
+        });
+
+        WaveReduceSolution { results }
+    }
+}
@@ -0,0 +1,97 @@
+use aslan_data::{DataNode, Direction};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// how the next edge of a walk is drawn from the edge weights, the default draws from the raw weights
+// temperature: weights are raised to 1/temperature, below one favours heavy edges, at or below zero keeps only the heaviest
+// top_k: only the k heaviest edges can be drawn
+// top_p: only the heaviest edges that together hold at least this share of the weight can be drawn
+// repetition_penalty: divides the weight of an edge back to the state two steps before, against a-b-a oscillation
+// fields left out of a request keep their default
+#[derive(Debug,Serialize, Deserialize,Clone,Copy,PartialEq)]
+#[serde(default)]
+pub struct SamplingStrategy {
+    pub temperature: f64,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f64>,
+    pub repetition_penalty: f64,
+}
+
+impl Default for SamplingStrategy {
+    fn default() -> Self {
+        SamplingStrategy {
+            temperature: 1.0,
+            top_k: None,
+            top_p: None,
+            repetition_penalty: 1.0,
+        }
+    }
+}
+
+impl SamplingStrategy {
+    // previous: the state two steps back, if the walk is that long
+    // none when the node has no edges to follow
+    pub(crate) fn choose<R: Rng + ?Sized>(&self, node: &DataNode, direction: Direction, previous: Option<f64>, rng: &mut R) -> Option<usize> {
+        // the raw weights are drawn from the alias table of the node in constant time
+        if *self == SamplingStrategy::default() {
+            return node.sample_edge(direction, rng);
+        }
+        let edges = node.get_edges(direction);
+        let mut weights: Vec<f64> = edges.iter().map(|x| if x.weight > 0.0 && x.weight.is_finite() { x.weight } else { 0.0 }).collect();
+        if !weights.iter().any(|x| *x > 0.0) {
+            return node.sample_edge(direction, rng);
+        }
+
+        if self.repetition_penalty > 1.0 {
+            if let Some(previous) = previous {
+                for (weight, edge) in weights.iter_mut().zip(edges.iter()) {
+                    if edge.value == previous {
+                        *weight /= self.repetition_penalty;
+                    }
+                }
+            }
+        }
+
+        // heaviest first, the sort is stable so ties keep the order of the edges
+        let mut order: Vec<usize> = (0..weights.len()).filter(|x| weights[*x] > 0.0).collect();
+        order.sort_by(|a, b| weights[*b].partial_cmp(&weights[*a]).unwrap_or(std::cmp::Ordering::Equal));
+
+        if self.temperature <= 0.0 {
+            return Some(order[0]);
+        }
+        if self.temperature != 1.0 {
+            // scaled by the heaviest edge so small temperatures do not underflow
+            let heaviest = weights[order[0]];
+            weights.iter_mut().for_each(|x| *x = (*x / heaviest).powf(1.0 / self.temperature));
+        }
+
+        let mut kept = order.len();
+        if let Some(top_k) = self.top_k {
+            kept = kept.min(top_k.max(1));
+        }
+        if let Some(top_p) = self.top_p {
+            let total: f64 = order.iter().map(|x| weights[*x]).sum();
+            let mut cumulative = 0.0;
+            for (rank, index) in order.iter().enumerate().take(kept) {
+                cumulative += weights[*index];
+                if cumulative >= top_p * total {
+                    kept = rank + 1;
+                    break;
+                }
+            }
+        }
+
+        let total: f64 = order.iter().take(kept).map(|x| weights[*x]).sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return Some(order[0]);
+        }
+        let mut random_number = rng.gen_range(0.0..total);
+        for index in order.iter().take(kept) {
+            random_number -= weights[*index];
+            if random_number <= 0.0 {
+                return Some(*index);
+            }
+        }
+        Some(order[kept - 1])
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+// rates how far a generated path is from the actual one, lower is better
+// both paths are moves, most metrics compare the running totals (levels) of the moves
+pub trait ResultScorer {
+    fn score(&self, generated: &[f64], actual: &[f64]) -> f64;
+}
+
+#[derive(Debug,Serialize, Deserialize,Clone,PartialEq)]
+pub enum ScoringMetric {
+    // absolute difference of the totals, blind to the shape of the path
+    SumDifference,
+    // root mean square and mean absolute error of the levels
+    Rmse,
+    Mae,
+    // share of moves that went the other way
+    DirectionalAccuracy,
+    // distance of the levels after aligning them in time, window limits how far a step can shift
+    DynamicTimeWarping { window: Option<usize> },
+    // difference of the largest peak to trough fall of the levels
+    MaxDrawdownDifference,
+    // weighted sum of other metrics, the weights also have to even out their scales
+    Combined(Vec<(f64, ScoringMetric)>),
+}
+
+impl Default for ScoringMetric {
+    fn default() -> Self {
+        ScoringMetric::SumDifference
+    }
+}
+
+impl ResultScorer for ScoringMetric {
+    fn score(&self, generated: &[f64], actual: &[f64]) -> f64 {
+        match self {
+            ScoringMetric::SumDifference => (generated.iter().sum::<f64>() - actual.iter().sum::<f64>()).abs(),
+            ScoringMetric::Rmse => {
+                let errors = level_errors(generated, actual);
+                if errors.is_empty() {
+                    return 0.0;
+                }
+                (errors.iter().map(|x| x.powi(2)).sum::<f64>() / errors.len() as f64).sqrt()
+            },
+            ScoringMetric::Mae => {
+                let errors = level_errors(generated, actual);
+                if errors.is_empty() {
+                    return 0.0;
+                }
+                errors.iter().map(|x| x.abs()).sum::<f64>() / errors.len() as f64
+            },
+            ScoringMetric::DirectionalAccuracy => {
+                let steps = generated.len().min(actual.len());
+                if steps == 0 {
+                    return 0.0;
+                }
+                let matching = generated.iter().zip(actual.iter()).filter(|(a, b)| direction(**a) == direction(**b)).count();
+                1.0 - matching as f64 / steps as f64
+            },
+            ScoringMetric::DynamicTimeWarping { window } => dynamic_time_warping(&levels(generated), &levels(actual), *window),
+            ScoringMetric::MaxDrawdownDifference => (max_drawdown(&levels(generated)) - max_drawdown(&levels(actual))).abs(),
+            ScoringMetric::Combined(metrics) => metrics.iter().map(|(weight, metric)| weight * metric.score(generated, actual)).sum(),
+        }
+    }
+}
+
+fn levels(moves: &[f64]) -> Vec<f64> {
+    let mut total = 0.0;
+    moves.iter().map(|x| {
+        total += x;
+        total
+    }).collect()
+}
+
+// compares the paths over their common length
+fn level_errors(generated: &[f64], actual: &[f64]) -> Vec<f64> {
+    levels(generated).iter().zip(levels(actual).iter()).map(|(a, b)| a - b).collect()
+}
+
+fn direction(value: f64) -> i8 {
+    if value > 0.0 {
+        1
+    } else if value < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+// largest fall from a previous peak, the path starts at a level of zero
+fn max_drawdown(levels: &Vec<f64>) -> f64 {
+    let mut peak: f64 = 0.0;
+    let mut drawdown: f64 = 0.0;
+    for level in levels.iter() {
+        peak = peak.max(*level);
+        drawdown = drawdown.max(peak - level);
+    }
+    drawdown
+}
+
+fn dynamic_time_warping(generated: &Vec<f64>, actual: &Vec<f64>, window: Option<usize>) -> f64 {
+    let (rows, columns) = (generated.len(), actual.len());
+    if rows == 0 || columns == 0 {
+        return 0.0;
+    }
+    // the window has to cover the difference in length or the end cannot be reached
+    let window = window.map(|x| x.max(rows.abs_diff(columns)));
+
+    let mut costs = vec![vec![f64::MAX; columns + 1]; rows + 1];
+    costs[0][0] = 0.0;
+    for row in 1..=rows {
+        let (start, end) = match window {
+            Some(window) => (row.saturating_sub(window).max(1), (row + window).min(columns)),
+            None => (1, columns),
+        };
+        for column in start..=end {
+            let cost = (generated[row - 1] - actual[column - 1]).abs();
+            let previous = costs[row - 1][column].min(costs[row][column - 1]).min(costs[row - 1][column - 1]);
+            costs[row][column] = cost + previous;
+        }
+    }
+    costs[rows][columns]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn level_metrics() {
+        // levels 1, 2 against 1, 0
+        let (generated, actual) = ([1.0, 1.0], [1.0, -1.0]);
+        assert!(close(ScoringMetric::SumDifference.score(&generated, &actual), 2.0));
+        assert!(close(ScoringMetric::Rmse.score(&generated, &actual), 2f64.sqrt()));
+        assert!(close(ScoringMetric::Mae.score(&generated, &actual), 1.0));
+        let combined = ScoringMetric::Combined(vec![(2.0, ScoringMetric::Mae), (1.0, ScoringMetric::SumDifference)]);
+        assert!(close(combined.score(&generated, &actual), 4.0));
+    }
+
+    #[test]
+    fn directional_accuracy_counts_flat_moves() {
+        let score = ScoringMetric::DirectionalAccuracy.score(&[1.0, -1.0, 0.0, 2.0], &[1.0, 1.0, 0.0, -2.0]);
+        assert!(close(score, 0.5));
+    }
+
+    #[test]
+    fn max_drawdown_from_the_highest_level() {
+        // levels 1, 3, -1, 0, -1
+        assert!(close(max_drawdown(&levels(&[1.0, 2.0, -4.0, 1.0, -1.0])), 4.0));
+        // the path starts at zero, so falling right away is a drawdown
+        assert!(close(max_drawdown(&levels(&[-2.0, 1.0])), 2.0));
+        assert!(close(max_drawdown(&levels(&[1.0, 1.0])), 0.0));
+        let score = ScoringMetric::MaxDrawdownDifference.score(&[1.0, 2.0, -4.0, 1.0, -1.0], &[-2.0, 1.0]);
+        assert!(close(score, 2.0));
+    }
+
+    #[test]
+    fn dynamic_time_warping_aligns_shifted_paths() {
+        // the same shape one step later costs nothing
+        assert!(close(dynamic_time_warping(&vec![0.0, 1.0, 2.0], &vec![0.0, 0.0, 1.0, 2.0], None), 0.0));
+        // the first and last levels have to be matched to something one away
+        assert!(close(dynamic_time_warping(&vec![1.0, 2.0, 3.0], &vec![2.0, 2.0, 2.0], None), 2.0));
+        // without a window the jump can be aligned, a zero window compares step by step
+        assert!(close(dynamic_time_warping(&vec![0.0, 5.0, 5.0], &vec![0.0, 0.0, 5.0], None), 0.0));
+        assert!(close(dynamic_time_warping(&vec![0.0, 5.0, 5.0], &vec![0.0, 0.0, 5.0], Some(0)), 5.0));
+        // a window narrower than the difference in length still reaches the end
+        assert!(close(dynamic_time_warping(&vec![0.0, 1.0, 2.0], &vec![0.0, 0.0, 1.0, 2.0], Some(0)), 0.0));
+        assert!(close(dynamic_time_warping(&vec![], &vec![1.0], None), 0.0));
+    }
+}
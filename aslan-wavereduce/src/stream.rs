@@ -0,0 +1,129 @@
+use aslan_data::DataNode;
+use rand::prelude::*;
+
+use crate::WaveReduce;
+
+// walks generated one at a time into a single reused buffer
+// memory stays flat however many of the number_of_results candidates are drawn
+pub struct WavePaths<'a, R: Rng + ?Sized> {
+    wavereduce: &'a WaveReduce,
+    data: &'a Vec<DataNode>,
+    rng: &'a mut R,
+    first_node_index: usize,
+    start_weights: Option<Vec<f64>>,
+    buffer: Vec<f64>,
+    generated: usize,
+}
+
+impl WaveReduce {
+    pub fn paths<'a, R: Rng + ?Sized>(&'a self, data: &'a Vec<DataNode>, rng: &'a mut R) -> WavePaths<'a, R> {
+        WavePaths {
+            wavereduce: self,
+            data,
+            rng,
+            first_node_index: WaveReduce::select_first_node_index(self.seed, data),
+            start_weights: self.start_weights(data),
+            buffer: Vec::with_capacity(self.number_of_cells),
+            generated: 0,
+        }
+    }
+}
+
+impl<'a, R: Rng + ?Sized> WavePaths<'a, R> {
+    // the next path, only valid until the following call
+    pub fn next_path(&mut self) -> Option<&[f64]> {
+        if self.generated >= self.wavereduce.number_of_results || self.data.is_empty() {
+            return None;
+        }
+        self.generated += 1;
+        let start_node_index = WaveReduce::start_node_index(self.first_node_index, &self.start_weights, self.rng);
+        self.wavereduce.walk_into(self.data, start_node_index, self.rng, &mut self.buffer);
+        Some(&self.buffer)
+    }
+
+    // number of candidates drawn so far
+    pub fn generated(&self) -> usize {
+        self.generated
+    }
+
+    // keeps the paths accept takes and stops as soon as there are wanted of them,
+    // or when the candidates run out
+    pub fn accepted<F: FnMut(&[f64]) -> bool>(&mut self, wanted: usize, mut accept: F) -> Vec<Vec<f64>> {
+        let mut accepted = Vec::with_capacity(wanted);
+        while accepted.len() < wanted {
+            match self.next_path() {
+                Some(path) => {
+                    if accept(path) {
+                        accepted.push(path.to_vec());
+                    }
+                },
+                None => break,
+            }
+        }
+        accepted
+    }
+}
+
+// owned paths, for when the buffer reuse of next_path is not needed
+impl<'a, R: Rng + ?Sized> Iterator for WavePaths<'a, R> {
+    type Item = Vec<f64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_path().map(|x| x.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aslan_data::Edge;
+
+    // 0 moves to 1 or 2, 1 always moves to itself and 2 has no edges, so walks through 2 stop after one cell
+    fn graph() -> Vec<DataNode> {
+        [(0.0, vec![1.0, 2.0]), (1.0, vec![1.0]), (2.0, vec![])].iter().map(|(average, targets)| {
+            let mut node = DataNode::new(*average);
+            for target in targets {
+                let mut edge = Edge::new(*target);
+                edge.score = 1.0;
+                edge.weight = 1.0;
+                node.forward_edges.push(edge);
+            }
+            node
+        }).collect()
+    }
+
+    #[test]
+    fn accepted_stops_after_wanted_paths() {
+        let data = graph();
+        let wavereduce = WaveReduce::new(0.0, 3, 100);
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut paths = wavereduce.paths(&data, &mut rng);
+
+        let accepted = paths.accepted(10, |path| path.len() == 3);
+        assert_eq!(accepted.len(), 10);
+        assert!(accepted.iter().all(|x| *x == vec![1.0, 1.0, 1.0]));
+        // the short walks drawn on the way are counted but not kept, and no more were drawn than needed
+        assert!(paths.generated() > 10 && paths.generated() < 100);
+
+        // the candidates run out before a filter nothing passes is satisfied
+        assert!(paths.accepted(10, |_| false).is_empty());
+        assert_eq!(paths.generated(), 100);
+        assert!(paths.next_path().is_none());
+    }
+
+    #[test]
+    fn next_path_reuses_the_buffer() {
+        let data = graph();
+        let wavereduce = WaveReduce::new(0.0, 3, 20);
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut paths = wavereduce.paths(&data, &mut rng);
+
+        let first = paths.next_path().unwrap().as_ptr();
+        for _ in 1..20 {
+            let path = paths.next_path().unwrap();
+            assert_eq!(path.as_ptr(), first);
+            assert!(path == [1.0, 1.0, 1.0] || path == [2.0]);
+        }
+        assert!(paths.next_path().is_none());
+    }
+}